@@ -0,0 +1,168 @@
+//! the optional configuration file and errors in the configuration
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::info;
+use serde::Deserialize;
+
+/// contents of the optional configuration file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// ordered list of date patterns replacing the built-in ones
+    pub date_patterns: Option<Vec<DatePatternConfig>>,
+    /// ordered list of rules assigning document types by words in the title
+    pub document_type_rules: Option<Vec<DocumentTypeRuleConfig>>,
+    /// api token, used unless given in the environment
+    pub api_token: Option<String>,
+    /// file containing the api token, used unless given in the environment
+    pub api_token_file: Option<PathBuf>,
+    /// how to authenticate: token, basic or login
+    pub auth_method: Option<String>,
+    /// user for basic authentication or login
+    pub username: Option<String>,
+    /// password for basic authentication or login
+    pub password: Option<String>,
+    /// file containing the password for basic authentication or login
+    pub password_file: Option<PathBuf>,
+}
+
+/// a date pattern entry of the configuration file, either a custom regex or a built-in pattern
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatePatternConfig {
+    pub name: Option<String>,
+    pub pattern: Option<String>,
+    pub builtin: Option<String>,
+}
+
+/// a document type rule entry of the configuration file, matching either keywords or a regex
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DocumentTypeRuleConfig {
+    pub keywords: Option<Vec<String>>,
+    pub pattern: Option<String>,
+    /// name of the document type in paperless
+    pub document_type: String,
+    /// whether to replace a document type the document already has, defaults to true
+    #[serde(rename = "override")]
+    pub override_existing: Option<bool>,
+}
+
+/// a missing or invalid setting
+#[derive(Debug)]
+pub enum Error {
+    /// a required setting is not set
+    Missing(String),
+    /// a setting has an invalid value
+    Invalid { setting: String, reason: String },
+    /// the configuration file cannot be read or parsed
+    File { path: String, reason: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Missing(name) => write!(f, "{name} is not set"),
+            Error::Invalid { setting, reason } => write!(f, "{setting} is invalid: {reason}"),
+            Error::File { path, reason } => write!(f, "configuration file {path}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    pub fn invalid(setting: &str, reason: impl fmt::Display) -> Error {
+        Error::Invalid {
+            setting: setting.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// parses an optional environment variable
+pub fn parse_env<T: FromStr>(name: &str) -> Result<Option<T>, Error>
+where
+    T::Err: fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|err| Error::invalid(name, format!("'{value}': {err}"))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// parses an optional environment variable holding a number of seconds, fractions allowed
+pub fn parse_seconds_env(name: &str) -> Result<Option<Duration>, Error> {
+    parse_env::<f64>(name)?
+        .map(|seconds| {
+            Duration::try_from_secs_f64(seconds)
+                .map_err(|_| Error::invalid(name, format!("'{seconds}' is no number of seconds")))
+        })
+        .transpose()
+}
+
+/// parses a flag (true/false, yes/no, on/off, 1/0), an empty value meaning false
+pub fn parse_flag(value: &str) -> Result<bool, String> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" | "" => Ok(false),
+        value => Err(format!("'{value}' is no flag, expected true or false")),
+    }
+}
+
+/// parses an optional environment variable holding a flag
+pub fn parse_flag_env(name: &str) -> Result<Option<bool>, Error> {
+    env::var(name)
+        .ok()
+        .map(|value| parse_flag(&value).map_err(|reason| Error::invalid(name, reason)))
+        .transpose()
+}
+
+/// reads a secret like a password or token from a file, as provided by docker or kubernetes
+/// secrets, ignoring trailing line breaks
+pub fn read_secret(setting: &str, path: &Path) -> Result<String, Error> {
+    let secret = fs::read_to_string(path).map_err(|err| {
+        Error::invalid(setting, format!("unable to read {}: {err}", path.display()))
+    })?;
+
+    let secret = secret.trim_end_matches(['\r', '\n']);
+    if secret.is_empty() {
+        return Err(Error::invalid(
+            setting,
+            format!("{} is empty", path.display()),
+        ));
+    }
+
+    Ok(secret.to_string())
+}
+
+impl Config {
+    /// loads the configuration file given in PAPERLESS_POST_CONSUME_CONFIG, if any
+    pub fn load() -> Result<Config, Error> {
+        let Ok(path) = env::var("PAPERLESS_POST_CONSUME_CONFIG") else {
+            return Ok(Config::default());
+        };
+
+        info!("using configuration file {path}");
+
+        let file_error = |reason: String| Error::File {
+            path: path.clone(),
+            reason,
+        };
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|err| file_error(format!("unable to read: {err}")))?;
+
+        toml::from_str(&contents).map_err(|err| file_error(format!("unable to parse: {err}")))
+    }
+}
@@ -0,0 +1,40 @@
+//! stripping dates from paperless-ngx document titles
+//!
+//! - [`title`] finds dates in titles and strips them, without any network access
+//! - [`paperless`] talks to the paperless-ngx rest api
+//! - [`metadata`] takes further metadata like the correspondent or tags from titles
+//! - [`pipeline`] combines them to rewrite documents
+//! - [`config`] reads the optional configuration file
+//!
+//! progress messages are emitted through the [`log`] crate, the library prints nothing itself.
+//!
+//! [`title::DateSettings::load`] reads the settings from the environment like the script does,
+//! the example spells them out instead:
+//!
+//! ```
+//! use paperless_post_consume::title::{
+//!     rewrite_title, DateOrder, DatePattern, DatePosition, DateSettings, PartialDates, RangeDate,
+//! };
+//!
+//! let date_settings = DateSettings {
+//!     patterns: DatePattern::builtins(DateOrder::DayMonthYear),
+//!     position: DatePosition::Start,
+//!     partial_dates: PartialDates::Off,
+//!     range_date: RangeDate::Start,
+//!     min_year: 1900,
+//!     max_year: 2100,
+//!     century_pivot: 70,
+//! };
+//!
+//! let rewrite = rewrite_title("2024-03-15 - Rechnung", &date_settings)
+//!     .unwrap()
+//!     .unwrap();
+//! assert_eq!(rewrite.title, "Rechnung");
+//! assert_eq!(rewrite.created_date.unwrap().to_string(), "2024-03-15");
+//! ```
+
+pub mod config;
+pub mod metadata;
+pub mod paperless;
+pub mod pipeline;
+pub mod title;
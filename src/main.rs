@@ -1,128 +1,519 @@
 use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+use std::process;
 
-use regex::Regex;
-use reqwest::StatusCode;
-use serde::{Deserialize, Serialize};
-use lazy_static;
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand};
 
-const PAPERLESS_API_URL_DEFAULT: &str = "http://localhost:8000/api/";
+use paperless_post_consume::config::{self, Config};
+use paperless_post_consume::metadata::{MetadataSettings, NameMatching};
+use paperless_post_consume::paperless::{self, ObjectKind};
+use paperless_post_consume::pipeline::{
+    take_metadata, Lookup, ProcessOutcome, Processor, Resolve, Summary,
+};
+use paperless_post_consume::title::{rewrite_title, DateSettings};
 
-#[derive(Debug, Serialize, Deserialize)]
-struct DocumentProperties {
-    title: String,
-    created_date: String,
+/// exit status used when some of multiple documents could not be processed
+const EXIT_DOCUMENTS_FAILED: i32 = 1;
+
+/// exit status used when the title starts with something that looks like a date but is not valid
+const EXIT_INVALID_DATE: i32 = 2;
+
+/// exit status used for missing or invalid settings and invalid command line usage
+const EXIT_CONFIG: i32 = 3;
+
+/// exit status used when paperless rejects the credentials or their permissions
+const EXIT_UNAUTHORIZED: i32 = 4;
+
+/// exit status used when the document does not exist
+const EXIT_NOT_FOUND: i32 = 5;
+
+/// exit status used when paperless cannot be reached
+const EXIT_CONNECTION: i32 = 6;
+
+/// exit status used when paperless fails to handle a request or responds unexpectedly
+const EXIT_SERVER: i32 = 7;
+
+/// exit status used when paperless rejects the update of a document
+const EXIT_REJECTED: i32 = 8;
+
+/// post consume script for paperless-ngx stripping dates from document titles
+///
+/// without a command the document given in DOCUMENT_ID is processed, as done by paperless.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// only print the planned changes, do not modify any document
+    #[arg(
+        long,
+        global = true,
+        env = "PAPERLESS_POST_CONSUME_DRY_RUN",
+        value_parser = config::parse_flag
+    )]
+    dry_run: bool,
+
+    /// print a json object describing the error in addition to the error message
+    #[arg(
+        long,
+        global = true,
+        env = "PAPERLESS_POST_CONSUME_JSON_ERRORS",
+        value_parser = config::parse_flag
+    )]
+    json_errors: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-lazy_static::lazy_static! {
-    static ref DATE_PATTERNS: [Regex; 2] = [
-        // match title for ISO date
-        Regex::new(r"^(?<year>[0-9]{4})-(?<month>[0-9]{2})-(?<day>[0-9]{2})\s*-?\s*").unwrap(),
-        // match title for German
-        Regex::new(r"^(?<day>[0-9]{2})\.(?<month>[0-9]{2})\.(?<year>[0-9]{4})\s*-?\s*").unwrap(),
-    ];
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// process the document given in DOCUMENT_ID (default)
+    PostConsume,
+    /// process the given documents
+    Process {
+        #[arg(required = true)]
+        document_ids: Vec<i32>,
+    },
+    /// process all existing documents matching the filters
+    Backfill(BackfillFilters),
+    /// show how titles would be rewritten, without contacting paperless
+    ///
+    /// titles are read one per line from the file or stdin if none are given.
+    TestTitle {
+        titles: Vec<String>,
+        /// file containing one title per line, - for stdin
+        #[arg(long, value_name = "PATH", conflicts_with = "titles")]
+        file: Option<PathBuf>,
+    },
+    /// check that the paperless api can be reached with the configured token
+    CheckConnection,
 }
 
-#[tokio::main]
-async fn main() {
-    println!("{} - {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+/// filters restricting the documents processed by a backfill
+#[derive(Debug, Default, Args)]
+struct BackfillFilters {
+    /// only documents having this tag id, may be given multiple times
+    #[arg(long = "tag", value_name = "ID")]
+    tags: Vec<i32>,
+    /// only documents of this correspondent id
+    #[arg(long, value_name = "ID")]
+    correspondent: Option<i32>,
+    /// only documents created after this date
+    #[arg(long, value_name = "YYYY-MM-DD")]
+    created_after: Option<NaiveDate>,
+    /// only documents created before this date
+    #[arg(long, value_name = "YYYY-MM-DD")]
+    created_before: Option<NaiveDate>,
+    /// only documents whose title contains this text
+    #[arg(long, value_name = "TEXT")]
+    title_contains: Option<String>,
+}
+
+impl BackfillFilters {
+    /// query parameters of the paperless document list implementing the filters
+    fn query(&self) -> Vec<(&'static str, String)> {
+        let mut query = Vec::new();
 
-    let document_id: i32 = env::var("DOCUMENT_ID")
-        .expect("DOCUMENT_ID environment variable is not set")
-        .parse()
-        .expect("unable to parse DOCUMENT_ID to integer");
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = self.tags.iter().map(i32::to_string).collect();
+            query.push(("tags__id__all", tags.join(",")));
+        }
+        if let Some(correspondent) = self.correspondent {
+            query.push(("correspondent__id", correspondent.to_string()));
+        }
+        if let Some(created_after) = self.created_after {
+            query.push(("created__date__gt", created_after.to_string()));
+        }
+        if let Some(created_before) = self.created_before {
+            query.push(("created__date__lt", created_before.to_string()));
+        }
+        if let Some(title_contains) = &self.title_contains {
+            query.push(("title__icontains", title_contains.clone()));
+        }
 
-    let api_token = env::var("PAPERLESS_API_TOKEN")
-        .expect("PAPERLESS_API_TOKEN environment variable is not set");
+        query
+    }
+}
 
-    let api_url = match env::var("PAPERLESS_API_URL") {
-        Ok(url) => {
-            println!("using provided api url: {url}");
-            url
+/// why the script failed, every kind of failure has its own exit status
+#[derive(Debug)]
+enum Failure {
+    Config(config::Error),
+    /// the input given on the command line cannot be used
+    Usage(String),
+    Api {
+        error: paperless::Error,
+        document_id: Option<i32>,
+    },
+    InvalidDate {
+        reason: String,
+        document_id: Option<i32>,
+    },
+    /// the number of documents that could not be processed
+    DocumentsFailed(usize),
+}
+
+impl Failure {
+    /// short name of the kind of failure for monitoring
+    fn kind(&self) -> &'static str {
+        match self {
+            Failure::Config(_) => "config",
+            Failure::Usage(_) => "usage",
+            Failure::Api { error, .. } => match error {
+                paperless::Error::Transport(_) => "connection",
+                paperless::Error::Unauthorized { .. } => "unauthorized",
+                paperless::Error::NotFound { .. } => "not_found",
+                paperless::Error::Validation { .. } => "rejected",
+                paperless::Error::AsnInUse { .. } => "asn_in_use",
+                paperless::Error::Server { .. } => "server",
+                paperless::Error::InvalidResponse(_) => "invalid_response",
+            },
+            Failure::InvalidDate { .. } => "invalid_date",
+            Failure::DocumentsFailed(_) => "documents_failed",
         }
-        Err(_) => {
-            println!("environment variable PAPERLESS_API_URL is not set, using default ({PAPERLESS_API_URL_DEFAULT})");
-            PAPERLESS_API_URL_DEFAULT.to_string()
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Config(_) | Failure::Usage(_) => EXIT_CONFIG,
+            Failure::Api { error, .. } => match error {
+                paperless::Error::Transport(_) => EXIT_CONNECTION,
+                paperless::Error::Unauthorized { .. } => EXIT_UNAUTHORIZED,
+                paperless::Error::NotFound { .. } => EXIT_NOT_FOUND,
+                paperless::Error::Validation { .. } | paperless::Error::AsnInUse { .. } => {
+                    EXIT_REJECTED
+                }
+                paperless::Error::Server { .. } | paperless::Error::InvalidResponse(_) => {
+                    EXIT_SERVER
+                }
+            },
+            Failure::InvalidDate { .. } => EXIT_INVALID_DATE,
+            Failure::DocumentsFailed(_) => EXIT_DOCUMENTS_FAILED,
         }
-    };
+    }
+
+    /// prints the failure as a single line to stderr, followed by a json object if requested
+    fn report(&self, json: bool) {
+        eprintln!("error: {self}");
+
+        if json {
+            let (status, document_id) = match self {
+                Failure::Api { error, document_id } => {
+                    (error.status().map(|status| status.as_u16()), *document_id)
+                }
+                Failure::InvalidDate { document_id, .. } => (None, *document_id),
+                _ => (None, None),
+            };
+
+            eprintln!(
+                "{}",
+                serde_json::json!({
+                    "error": self.kind(),
+                    "exit_code": self.exit_code(),
+                    "message": self.to_string(),
+                    "status": status,
+                    "document_id": document_id,
+                })
+            );
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Config(err) => write!(f, "{err}"),
+            Failure::Usage(message) => write!(f, "{message}"),
+            Failure::Api {
+                error,
+                document_id: Some(document_id),
+            } => write!(f, "document {document_id}: {error}"),
+            Failure::Api { error, .. } => write!(f, "{error}"),
+            Failure::InvalidDate {
+                reason,
+                document_id: Some(document_id),
+            } => write!(f, "document {document_id}: {reason}"),
+            Failure::InvalidDate { reason, .. } => write!(f, "{reason}"),
+            Failure::DocumentsFailed(failed) => write!(f, "failed to process {failed} document(s)"),
+        }
+    }
+}
+
+impl From<config::Error> for Failure {
+    fn from(err: config::Error) -> Failure {
+        Failure::Config(err)
+    }
+}
+
+impl From<paperless::Error> for Failure {
+    fn from(error: paperless::Error) -> Failure {
+        Failure::Api {
+            error,
+            document_id: None,
+        }
+    }
+}
+
+/// prints the summary, failing if any document failed
+fn finish(summary: Summary) -> Result<(), Failure> {
+    println!("finished: {summary}");
+
+    if summary.failed > 0 {
+        return Err(Failure::DocumentsFailed(summary.failed));
+    }
+
+    Ok(())
+}
+
+/// processes the document paperless just consumed
+async fn post_consume(processor: &Processor) -> Result<(), Failure> {
+    let document_id: i32 = config::parse_env("DOCUMENT_ID")?
+        .ok_or_else(|| config::Error::Missing("DOCUMENT_ID".to_string()))?;
 
     println!("working on document id {document_id}");
 
-    let request_url = format!("{api_url}documents/{document_id}/");
-
-    let client = reqwest::Client::new();
-    let response = client
-        .get(&request_url)
-        .header(reqwest::header::AUTHORIZATION, format!("Token {api_token}"))
-        .send()
-        .await
-        .expect("unable to fetch document data");
-
-    // check http return code
-    match response.status() {
-        StatusCode::OK => (),
-        StatusCode::UNAUTHORIZED => panic!(
-            "got a 401 response - it seems the api token does not work: {:#}",
-            response.text().await.unwrap()
-        ),
-        _ => panic!(
-            "something unexpected happened: {:#}",
-            response.text().await.unwrap()
-        ),
-    }
-
-    let document_data = response
-        .json::<DocumentProperties>()
-        .await
-        .expect("unable to parse document data");
-
-    println!(
-        "document properties for document {document_id}: {:#?}",
-        document_data
-    );
-
-    let matches = DATE_PATTERNS
-        .iter()
-        .find_map(|pattern| pattern.captures(&document_data.title));
-
-    let Some(date_parts) = matches else {
-        println!("no date match found - nothing to do");
-        return;
+    match processor.process_document_id(document_id).await {
+        Ok(ProcessOutcome::InvalidDate(invalid)) => Err(Failure::InvalidDate {
+            reason: invalid.to_string(),
+            document_id: Some(document_id),
+        }),
+        Ok(_) => Ok(()),
+        Err(error) => Err(Failure::Api {
+            error,
+            document_id: Some(document_id),
+        }),
+    }
+}
+
+/// processes the given documents one after another
+async fn process(processor: &Processor, document_ids: &[i32]) -> Result<(), Failure> {
+    let mut summary = Summary::default();
+
+    for &document_id in document_ids {
+        println!("working on document id {document_id}");
+
+        let result = processor.process_document_id(document_id).await;
+        summary.record(document_id, result);
+    }
+
+    finish(summary)
+}
+
+/// processes all existing documents matching the filters
+async fn backfill(processor: &Processor, filters: &BackfillFilters) -> Result<(), Failure> {
+    let documents = processor.client.list_documents(&filters.query()).await?;
+
+    println!("found {} documents to process", documents.len());
+
+    let mut summary = Summary::default();
+
+    for document_data in documents {
+        let document_id = document_data.id;
+        println!("working on document id {document_id}");
+
+        let result = processor.process_document(document_data).await;
+        summary.record(document_id, result);
+    }
+
+    finish(summary)
+}
+
+/// takes every name from the title as if paperless knew it, as names are not looked up when
+/// testing titles
+struct Offline;
+
+impl Resolve for Offline {
+    async fn resolve(
+        &self,
+        _kind: ObjectKind,
+        _name: &str,
+        _matching: NameMatching,
+        _create: bool,
+    ) -> Result<Lookup, paperless::Error> {
+        Ok(Lookup::Assumed)
+    }
+}
+
+/// shows what would happen to titles, without contacting paperless
+///
+/// fails with the invalid date status if any of the titles contains an invalid date.
+async fn test_titles(
+    titles: &[String],
+    file: Option<&PathBuf>,
+    date_settings: &DateSettings,
+    metadata_settings: &MetadataSettings,
+) -> Result<(), Failure> {
+    let titles: Vec<String> = match (titles, file) {
+        ([], file) => {
+            let reader: Box<dyn BufRead> = match file {
+                Some(path) if path.as_os_str() != "-" => {
+                    Box::new(io::BufReader::new(fs::File::open(path).map_err(|err| {
+                        Failure::Usage(format!("unable to open {}: {err}", path.display()))
+                    })?))
+                }
+                _ => Box::new(io::stdin().lock()),
+            };
+            reader
+                .lines()
+                .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+                .collect::<Result<_, _>>()
+                .map_err(|err| Failure::Usage(format!("unable to read titles: {err}")))?
+        }
+        (titles, _) => titles.to_vec(),
     };
 
-    let new_document_title = &document_data.title[date_parts[0].len()..];
+    let mut invalid = 0;
+
+    for title in &titles {
+        println!("{title}");
+
+        let (new_title, date_stripped) = match rewrite_title(title, date_settings) {
+            Ok(Some(rewrite)) => {
+                let created_date = match rewrite.created_date {
+                    Some(date) => date.format("%Y-%m-%d").to_string(),
+                    None => "unchanged".to_string(),
+                };
+
+                println!("  pattern:       {}", rewrite.pattern);
+                println!("  date:          {}", rewrite.date_text);
+                println!("  created date:  {created_date}");
+                if let Some((first_day, last_day)) = rewrite.range {
+                    println!("  range:         {first_day} - {last_day}");
+                }
+                (rewrite.title, true)
+            }
+            Ok(None) => {
+                println!("  no date match found");
+                (title.clone(), false)
+            }
+            Err(err) => {
+                println!("  {err}");
+                invalid += 1;
+                continue;
+            }
+        };
+
+        let metadata = take_metadata(new_title, date_stripped, metadata_settings, &Offline).await?;
+        if let Some(asn) = metadata.archive_serial_number {
+            println!("  asn:           {asn}");
+        }
+        if let Some(rule) = metadata.document_type_rule {
+            println!("  document type: {}", rule.document_type);
+        }
+        if !metadata.tags.is_empty() {
+            let names: Vec<&str> = metadata
+                .tags
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect();
+            println!("  tags:          {}", names.join(", "));
+        }
+        if let Some((name, _)) = &metadata.correspondent {
+            println!("  correspondent: {name}");
+        }
+
+        let new_title = metadata.title;
+        if new_title != *title {
+            println!("  title:         {new_title}");
+        }
+    }
+
+    if invalid > 0 {
+        return Err(Failure::InvalidDate {
+            reason: format!(
+                "{invalid} of {} titles contain an invalid date",
+                titles.len()
+            ),
+            document_id: None,
+        });
+    }
+
+    Ok(())
+}
+
+/// prints the messages of the library to stdout, where paperless collects the output of
+/// post-consume scripts
+struct StdoutLogger;
+
+impl log::Log for StdoutLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info
+            && metadata.target().starts_with("paperless_post_consume")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            log::Level::Warn => println!("warning: {}", record.args()),
+            _ => println!("{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StdoutLogger = StdoutLogger;
+
+async fn run(cli: &Cli) -> Result<(), Failure> {
+    let config = Config::load()?;
+    let date_settings = DateSettings::load(&config)?;
+    let metadata_settings = MetadataSettings::load(&config)?;
+
+    if let Some(Command::TestTitle { titles, file }) = &cli.command {
+        return test_titles(titles, file.as_ref(), &date_settings, &metadata_settings).await;
+    }
+
+    if cli.dry_run {
+        println!("dry run - documents will not be modified");
+    }
 
-    // contruct new document properties
-    let new_document_data = DocumentProperties {
-        title: new_document_title.to_string(),
-        created_date: format!(
-            "{}-{}-{}",
-            &date_parts["year"], &date_parts["month"], &date_parts["day"]
-        ),
+    let processor = Processor::from_env(&config, date_settings, metadata_settings, cli.dry_run)?;
+
+    match &cli.command {
+        None | Some(Command::PostConsume) => post_consume(&processor).await,
+        Some(Command::Process { document_ids }) => process(&processor, document_ids).await,
+        Some(Command::Backfill(filters)) => backfill(&processor, filters).await,
+        Some(Command::CheckConnection) => {
+            let count = processor.client.check_connection().await?;
+            println!("connection ok - {count} documents visible");
+            Ok(())
+        }
+        Some(Command::TestTitle { .. }) => unreachable!("handled without connection"),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // paperless passes details of the consumed document as positional arguments, these are
+    // available from the environment as well and are ignored
+    let mut args: Vec<String> = env::args().collect();
+    if args.get(1).is_some_and(|arg| arg.parse::<i32>().is_ok()) {
+        args.truncate(1);
+    }
+
+    let cli = match Cli::try_parse_from(args) {
+        Ok(cli) => cli,
+        // help and version are no errors
+        Err(err) if !err.use_stderr() => err.exit(),
+        Err(err) => {
+            let _ = err.print();
+            process::exit(EXIT_CONFIG);
+        }
     };
 
-    println!(
-        "new document properties for document {document_id}: {:#?}",
-        new_document_data
-    );
-
-    let response = client
-        .patch(&request_url)
-        .header(reqwest::header::AUTHORIZATION, format!("Token {api_token}"))
-        .json(&new_document_data)
-        .send()
-        .await
-        .expect("unable to set new document properties");
-
-    // check http return code
-    match response.status() {
-        StatusCode::OK => println!("successfully renamed document and updated created date"),
-        StatusCode::UNAUTHORIZED => panic!(
-            "got a 401 response - it seems the api token does not work: {:#}",
-            response.text().await.unwrap()
-        ),
-        _ => panic!(
-            "something unexpected happened: {:#}",
-            response.text().await.unwrap()
-        ),
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Info);
+    }
+
+    println!("{} - {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+    if let Err(failure) = run(&cli).await {
+        failure.report(cli.json_errors);
+        process::exit(failure.exit_code());
     }
 }
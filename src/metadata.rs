@@ -0,0 +1,788 @@
+//! metadata taken from the title once the date is stripped, without any network access
+
+use std::env;
+use std::ops::Range;
+
+use lazy_static::lazy_static;
+use log::warn;
+use regex::{Regex, RegexBuilder};
+
+use crate::config::{self, Config, DocumentTypeRuleConfig};
+use crate::title::strip_range;
+
+/// least similarity of two names, between 0 and 1, to be considered a fuzzy match
+const FUZZY_MATCH_THRESHOLD: f64 = 0.8;
+
+lazy_static! {
+    /// dash between two segments of a title, e.g. in "Telekom - Rechnung"
+    static ref SEGMENT_SEPARATOR: Regex = Regex::new(r"\s+[-–—]\s+").unwrap();
+    /// tag written as hashtag, e.g. "#medical", not within a word
+    static ref HASHTAG: Regex = Regex::new(r"(?:^|\s)(#([\p{L}\p{N}_][\p{L}\p{N}_/-]*))").unwrap();
+    /// archive serial number written with its prefix, e.g. "ASN1234" or "ASN 1234"
+    static ref ASN_PREFIX: Regex = Regex::new(r"(?i)\b(ASN[ -]?([0-9]+))\b").unwrap();
+    /// archive serial number written as numeric hashtag, e.g. "#00042"
+    static ref ASN_HASHTAG: Regex = Regex::new(r"(?:^|\s)(#([0-9]+))\b").unwrap();
+    /// tag written in brackets, e.g. "[Steuer]"
+    static ref BRACKET_TAG: Regex = Regex::new(r"(\[\s*([^\[\]]*[^\[\]\s])\s*\])").unwrap();
+}
+
+/// which of the dash-delimited segments of a title to take
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    First,
+    Last,
+    /// the segment at the position, starting at one
+    Nth(usize),
+}
+
+impl Segment {
+    fn from_setting(name: &str, setting: &str) -> Result<Option<Segment>, config::Error> {
+        match setting.trim() {
+            "off" | "" => Ok(None),
+            "first" => Ok(Some(Segment::First)),
+            "last" => Ok(Some(Segment::Last)),
+            setting => match setting.parse() {
+                Ok(position) if position > 0 => Ok(Some(Segment::Nth(position))),
+                _ => Err(config::Error::invalid(
+                    name,
+                    format!("unknown value '{setting}', expected off, first, last or a position"),
+                )),
+            },
+        }
+    }
+}
+
+/// how a name taken from a title is compared with the names known to paperless
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameMatching {
+    Exact,
+    IgnoreCase,
+    /// ignores case, punctuation and whitespace and tolerates small typos, also matching names
+    /// starting with the other name, e.g. "Telekom" and "Telekom AG"
+    Fuzzy,
+}
+
+impl NameMatching {
+    fn from_setting(name: &str, setting: &str) -> Result<NameMatching, config::Error> {
+        match setting.trim() {
+            "exact" => Ok(NameMatching::Exact),
+            "ignore-case" => Ok(NameMatching::IgnoreCase),
+            "fuzzy" => Ok(NameMatching::Fuzzy),
+            setting => Err(config::Error::invalid(
+                name,
+                format!("unknown value '{setting}', expected exact, ignore-case or fuzzy"),
+            )),
+        }
+    }
+
+    /// the object whose name matches best, none if no name or several names match equally well
+    pub fn find<'a, T>(
+        self,
+        name: &str,
+        objects: &'a [T],
+        object_name: impl Fn(&T) -> &str,
+    ) -> Option<&'a T> {
+        match self.candidates(name, objects, &object_name)[..] {
+            [] => None,
+            [object] => Some(object),
+            ref candidates => {
+                let names: Vec<&str> = candidates
+                    .iter()
+                    .map(|object| object_name(object))
+                    .collect();
+                warn!("'{name}' matches {} equally well", names.join(", "));
+                None
+            }
+        }
+    }
+
+    /// the objects whose names match best, more than one if they match equally well
+    pub fn candidates<'a, T>(
+        self,
+        name: &str,
+        objects: &'a [T],
+        object_name: impl Fn(&T) -> &str,
+    ) -> Vec<&'a T> {
+        match self {
+            NameMatching::Exact => objects
+                .iter()
+                .filter(|object| object_name(object) == name)
+                .collect(),
+            NameMatching::IgnoreCase => {
+                let name = name.to_lowercase();
+                objects
+                    .iter()
+                    .filter(|object| object_name(object).to_lowercase() == name)
+                    .collect()
+            }
+            NameMatching::Fuzzy => {
+                let matches: Vec<_> = objects
+                    .iter()
+                    .map(|object| (object, fuzzy_similarity(name, object_name(object))))
+                    .filter(|(_, (similarity, _))| *similarity >= FUZZY_MATCH_THRESHOLD)
+                    .collect();
+                let Some(best) = matches
+                    .iter()
+                    .map(|(_, score)| *score)
+                    .max_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+                else {
+                    return Vec::new();
+                };
+
+                matches
+                    .into_iter()
+                    .filter(|(_, score)| *score == best)
+                    .map(|(object, _)| object)
+                    .collect()
+            }
+        }
+    }
+}
+
+/// similarity of two names, comparing either name with the leading words of the other as well,
+/// along with whether the whole names were compared
+///
+/// comparing leading words lets "Telekom" match "Telekom AG", while the flag prefers a name equal
+/// to it over one merely starting with it.
+fn fuzzy_similarity(name: &str, other: &str) -> (f64, bool) {
+    let name = fuzzy_words(name);
+    let other = fuzzy_words(other);
+
+    let whole = strsim::normalized_levenshtein(&name.concat(), &other.concat());
+    let best_leading = |words: &[String], full: &[String]| {
+        (1..words.len())
+            .map(|count| strsim::normalized_levenshtein(&words[..count].concat(), &full.concat()))
+            .fold(0.0, f64::max)
+    };
+    let leading = best_leading(&name, &other).max(best_leading(&other, &name));
+
+    if whole >= leading {
+        (whole, true)
+    } else {
+        (leading, false)
+    }
+}
+
+/// the normalized words of a name, e.g. "telekom" and "ag" for "Telekom AG"
+fn fuzzy_words(name: &str) -> Vec<String> {
+    name.split_whitespace()
+        .map(fuzzy_normalize)
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// lowercase letters and digits of a name, e.g. "telekomag" for "Telekom AG"
+fn fuzzy_normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// how tags are written in titles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagSyntax {
+    /// "#medical"
+    Hashtag,
+    /// "[Steuer]"
+    Bracket,
+    Both,
+}
+
+impl TagSyntax {
+    fn from_setting(name: &str, setting: &str) -> Result<Option<TagSyntax>, config::Error> {
+        match setting.trim() {
+            "off" | "" => Ok(None),
+            "hashtag" => Ok(Some(TagSyntax::Hashtag)),
+            "bracket" => Ok(Some(TagSyntax::Bracket)),
+            "both" => Ok(Some(TagSyntax::Both)),
+            setting => Err(config::Error::invalid(
+                name,
+                format!("unknown value '{setting}', expected off, hashtag, bracket or both"),
+            )),
+        }
+    }
+
+    fn patterns(self) -> Vec<&'static Regex> {
+        match self {
+            TagSyntax::Hashtag => vec![&HASHTAG],
+            TagSyntax::Bracket => vec![&BRACKET_TAG],
+            TagSyntax::Both => vec![&HASHTAG, &BRACKET_TAG],
+        }
+    }
+}
+
+/// how archive serial numbers are written in titles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsnSyntax {
+    /// "ASN1234"
+    Prefix,
+    /// "#00042", taking precedence over hashtags naming tags
+    Hashtag,
+    Both,
+}
+
+impl AsnSyntax {
+    fn from_setting(name: &str, setting: &str) -> Result<Option<AsnSyntax>, config::Error> {
+        match setting.trim() {
+            "off" | "" => Ok(None),
+            "prefix" => Ok(Some(AsnSyntax::Prefix)),
+            "hashtag" => Ok(Some(AsnSyntax::Hashtag)),
+            "both" => Ok(Some(AsnSyntax::Both)),
+            setting => Err(config::Error::invalid(
+                name,
+                format!("unknown value '{setting}', expected off, prefix, hashtag or both"),
+            )),
+        }
+    }
+
+    fn patterns(self) -> Vec<&'static Regex> {
+        match self {
+            AsnSyntax::Prefix => vec![&ASN_PREFIX],
+            AsnSyntax::Hashtag => vec![&ASN_HASHTAG],
+            AsnSyntax::Both => vec![&ASN_PREFIX, &ASN_HASHTAG],
+        }
+    }
+}
+
+/// how the correspondent is taken from the title
+#[derive(Debug, Clone, Copy)]
+pub struct CorrespondentSettings {
+    pub segment: Segment,
+    pub matching: NameMatching,
+    /// create correspondents not known to paperless yet
+    pub create: bool,
+}
+
+/// how tags are taken from the title
+#[derive(Debug, Clone, Copy)]
+pub struct TagSettings {
+    pub syntax: TagSyntax,
+    pub matching: NameMatching,
+    /// create tags not known to paperless yet
+    pub create: bool,
+}
+
+/// assigns a document type to documents whose title matches
+#[derive(Debug, Clone)]
+pub struct DocumentTypeRule {
+    /// matched anywhere in the title, ignoring case unless the pattern says otherwise
+    pattern: Regex,
+    /// name of the document type in paperless
+    pub document_type: String,
+    /// whether to replace a document type the document already has
+    pub override_existing: bool,
+}
+
+impl DocumentTypeRule {
+    /// turns a rule entry of the configuration file into a rule, matching its keywords as whole
+    /// words or its pattern as given
+    ///
+    /// fails unless exactly one of keywords and pattern is set or if the regex is invalid.
+    fn configured(
+        index: usize,
+        entry: &DocumentTypeRuleConfig,
+    ) -> Result<DocumentTypeRule, config::Error> {
+        let setting = format!("document type rule #{}", index + 1);
+
+        let pattern = match (&entry.keywords, &entry.pattern) {
+            (Some(keywords), None) if !keywords.is_empty() => {
+                let keywords: Vec<String> = keywords
+                    .iter()
+                    .map(|keyword| regex::escape(keyword.trim()))
+                    .collect();
+                format!(r"\b(?:{})\b", keywords.join("|"))
+            }
+            (None, Some(pattern)) => pattern.clone(),
+            _ => {
+                return Err(config::Error::invalid(
+                    &setting,
+                    "exactly one of keywords or pattern must be set",
+                ))
+            }
+        };
+
+        let pattern = RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|err| config::Error::invalid(&setting, err))?;
+
+        Ok(DocumentTypeRule {
+            pattern,
+            document_type: entry.document_type.clone(),
+            override_existing: entry.override_existing.unwrap_or(true),
+        })
+    }
+}
+
+/// what to take from titles besides the date
+#[derive(Debug, Clone, Default)]
+pub struct MetadataSettings {
+    /// none unless taking the correspondent from the title is enabled
+    pub correspondent: Option<CorrespondentSettings>,
+    /// none unless taking tags from the title is enabled
+    pub tags: Option<TagSettings>,
+    /// rules of the configuration file, the first one matching the title applies
+    pub document_type_rules: Vec<DocumentTypeRule>,
+    /// none unless taking the archive serial number from the title is enabled
+    pub asn: Option<AsnSyntax>,
+}
+
+impl MetadataSettings {
+    /// reads PAPERLESS_CORRESPONDENT_SEGMENT, PAPERLESS_CORRESPONDENT_MATCHING,
+    /// PAPERLESS_CORRESPONDENT_CREATE, PAPERLESS_TAG_SYNTAX, PAPERLESS_TAG_MATCHING and
+    /// PAPERLESS_TAG_CREATE, PAPERLESS_ASN_SYNTAX and the document type rules of the configuration
+    /// file
+    pub fn load(config: &Config) -> Result<MetadataSettings, config::Error> {
+        let segment = match env::var("PAPERLESS_CORRESPONDENT_SEGMENT") {
+            Ok(setting) => Segment::from_setting("PAPERLESS_CORRESPONDENT_SEGMENT", &setting)?,
+            Err(_) => None,
+        };
+
+        let correspondent = match segment {
+            Some(segment) => Some(CorrespondentSettings {
+                segment,
+                matching: matching_env("PAPERLESS_CORRESPONDENT_MATCHING")?,
+                create: config::parse_flag_env("PAPERLESS_CORRESPONDENT_CREATE")?.unwrap_or(false),
+            }),
+            None => None,
+        };
+
+        let syntax = match env::var("PAPERLESS_TAG_SYNTAX") {
+            Ok(setting) => TagSyntax::from_setting("PAPERLESS_TAG_SYNTAX", &setting)?,
+            Err(_) => None,
+        };
+
+        let tags = match syntax {
+            Some(syntax) => Some(TagSettings {
+                syntax,
+                matching: matching_env("PAPERLESS_TAG_MATCHING")?,
+                create: config::parse_flag_env("PAPERLESS_TAG_CREATE")?.unwrap_or(false),
+            }),
+            None => None,
+        };
+
+        let document_type_rules = config
+            .document_type_rules
+            .iter()
+            .flatten()
+            .enumerate()
+            .map(|(index, entry)| DocumentTypeRule::configured(index, entry))
+            .collect::<Result<_, _>>()?;
+
+        let asn = match env::var("PAPERLESS_ASN_SYNTAX") {
+            Ok(setting) => AsnSyntax::from_setting("PAPERLESS_ASN_SYNTAX", &setting)?,
+            Err(_) => None,
+        };
+
+        Ok(MetadataSettings {
+            correspondent,
+            tags,
+            document_type_rules,
+            asn,
+        })
+    }
+}
+
+/// reads the name matching from an environment variable, ignoring case by default
+fn matching_env(name: &str) -> Result<NameMatching, config::Error> {
+    match env::var(name) {
+        Ok(setting) => NameMatching::from_setting(name, &setting),
+        Err(_) => Ok(NameMatching::IgnoreCase),
+    }
+}
+
+/// splits a segment off a title, returning the segment and the remaining title
+///
+/// a title consisting of a single segment is left alone, as the whole title is hardly a name.
+pub fn take_segment(title: &str, segment: Segment) -> Option<(String, String)> {
+    let mut segments: Vec<&str> = SEGMENT_SEPARATOR
+        .split(title.trim())
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    if segments.len() < 2 {
+        return None;
+    }
+
+    let index = match segment {
+        Segment::First => 0,
+        Segment::Last => segments.len() - 1,
+        Segment::Nth(position) if position <= segments.len() => position - 1,
+        Segment::Nth(_) => return None,
+    };
+
+    let taken = segments.remove(index).to_string();
+    Some((taken, segments.join(" - ")))
+}
+
+/// splits the first archive serial number off a title, returning the number and the remaining
+/// title
+///
+/// numbers too large for paperless are no archive serial numbers.
+pub fn take_asn(title: &str, syntax: AsnSyntax) -> Option<(u32, String)> {
+    syntax
+        .patterns()
+        .into_iter()
+        .flat_map(|pattern| pattern.captures_iter(title))
+        .filter_map(|captures| {
+            let asn = captures[2].parse().ok()?;
+            Some((asn, captures.get(1).unwrap().range()))
+        })
+        .min_by_key(|(_, range)| range.start)
+        .map(|(asn, range)| (asn, strip_range(title, range)))
+}
+
+/// a tag written in a title
+#[derive(Debug, Clone)]
+struct TagToken {
+    name: String,
+    /// position of the whole token, including "#" or the brackets
+    range: Range<usize>,
+}
+
+/// the tags written in the title, in order of appearance
+fn tag_tokens(title: &str, syntax: TagSyntax) -> Vec<TagToken> {
+    let mut tokens: Vec<TagToken> = syntax
+        .patterns()
+        .into_iter()
+        .flat_map(|pattern| pattern.captures_iter(title))
+        .map(|captures| TagToken {
+            name: captures[2].to_string(),
+            range: captures.get(1).unwrap().range(),
+        })
+        .collect();
+
+    tokens.sort_by_key(|token| token.range.start);
+    tokens
+}
+
+/// names of the tags written in the title, each name once
+pub fn find_tags(title: &str, syntax: TagSyntax) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+
+    for token in tag_tokens(title, syntax) {
+        if !names.contains(&token.name) {
+            names.push(token.name);
+        }
+    }
+
+    names
+}
+
+/// removes the tags of the given names from the title, leaving any others
+pub fn strip_tags(title: &str, syntax: TagSyntax, names: &[String]) -> String {
+    let mut title = title.to_string();
+
+    // positions change with every token removed, so the tokens are searched again each time
+    while let Some(token) = tag_tokens(&title, syntax)
+        .into_iter()
+        .find(|token| names.contains(&token.name))
+    {
+        title = strip_range(&title, token.range);
+    }
+
+    title
+}
+
+/// the first rule matching the title
+pub fn find_document_type<'a>(
+    title: &str,
+    rules: &'a [DocumentTypeRule],
+) -> Option<&'a DocumentTypeRule> {
+    rules.iter().find(|rule| rule.pattern.is_match(title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take(title: &str, segment: Segment) -> Option<(String, String)> {
+        take_segment(title, segment)
+    }
+
+    fn pair(taken: &str, title: &str) -> Option<(String, String)> {
+        Some((taken.to_string(), title.to_string()))
+    }
+
+    #[test]
+    fn segments() {
+        let title = "Telekom - Rechnung - März";
+
+        assert_eq!(
+            take(title, Segment::First),
+            pair("Telekom", "Rechnung - März")
+        );
+        assert_eq!(
+            take(title, Segment::Last),
+            pair("März", "Telekom - Rechnung")
+        );
+        assert_eq!(
+            take(title, Segment::Nth(2)),
+            pair("Rechnung", "Telekom - März")
+        );
+        assert_eq!(take(title, Segment::Nth(4)), None);
+        assert_eq!(
+            take("Telekom – Rechnung", Segment::First),
+            pair("Telekom", "Rechnung")
+        );
+
+        // a single segment or dashes within words do not count
+        assert_eq!(take("Telekom Rechnung", Segment::First), None);
+        assert_eq!(take("Telekom-Rechnung", Segment::First), None);
+    }
+
+    #[test]
+    fn segment_setting() {
+        let parse = |setting| Segment::from_setting("SEGMENT", setting).ok();
+
+        assert_eq!(parse("off"), Some(None));
+        assert_eq!(parse("first"), Some(Some(Segment::First)));
+        assert_eq!(parse(" 2 "), Some(Some(Segment::Nth(2))));
+        assert_eq!(parse("0"), None);
+        assert_eq!(parse("middle"), None);
+    }
+
+    #[test]
+    fn tags() {
+        let title = "Arztrechnung #medical [Steuer 2024] #tax";
+
+        assert_eq!(find_tags(title, TagSyntax::Hashtag), ["medical", "tax"]);
+        assert_eq!(find_tags(title, TagSyntax::Bracket), ["Steuer 2024"]);
+        assert_eq!(
+            find_tags(title, TagSyntax::Both),
+            ["medical", "Steuer 2024", "tax"]
+        );
+        assert_eq!(find_tags("#tax Rechnung #tax", TagSyntax::Hashtag), ["tax"]);
+
+        // hashtags within words are no tags
+        assert!(find_tags("C# Buch", TagSyntax::Hashtag).is_empty());
+        assert!(find_tags("Rechnung [ ]", TagSyntax::Bracket).is_empty());
+    }
+
+    #[test]
+    fn stripping_tags() {
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            strip_tags(
+                "Arztrechnung #medical #tax",
+                TagSyntax::Hashtag,
+                &names(&["medical", "tax"])
+            ),
+            "Arztrechnung"
+        );
+        assert_eq!(
+            strip_tags(
+                "Arztrechnung #medical #tax",
+                TagSyntax::Hashtag,
+                &names(&["tax"])
+            ),
+            "Arztrechnung #medical"
+        );
+        assert_eq!(
+            strip_tags(
+                "[Steuer] Lohnsteuerbescheinigung",
+                TagSyntax::Both,
+                &names(&["Steuer"])
+            ),
+            "Lohnsteuerbescheinigung"
+        );
+        assert_eq!(
+            strip_tags(
+                "Telekom - [Steuer] - Rechnung",
+                TagSyntax::Bracket,
+                &names(&["Steuer"])
+            ),
+            "Telekom - Rechnung"
+        );
+    }
+
+    #[test]
+    fn archive_serial_numbers() {
+        let asn = |title: &str, syntax| take_asn(title, syntax);
+        let taken = |number: u32, title: &str| Some((number, title.to_string()));
+
+        assert_eq!(
+            asn("Rechnung ASN1234", AsnSyntax::Prefix),
+            taken(1234, "Rechnung")
+        );
+        assert_eq!(
+            asn("asn 42 Rechnung", AsnSyntax::Prefix),
+            taken(42, "Rechnung")
+        );
+        assert_eq!(
+            asn("Vertrag - ASN-7", AsnSyntax::Prefix),
+            taken(7, "Vertrag")
+        );
+        assert_eq!(
+            asn("Vertrag #00042", AsnSyntax::Hashtag),
+            taken(42, "Vertrag")
+        );
+        assert_eq!(asn("Vertrag #00042", AsnSyntax::Prefix), None);
+        assert_eq!(asn("Vertrag ASN1", AsnSyntax::Hashtag), None);
+
+        // the first number is taken
+        assert_eq!(
+            asn("#5 Vertrag ASN6", AsnSyntax::Both),
+            taken(5, "Vertrag ASN6")
+        );
+
+        // no numbers within words or too large for paperless
+        assert_eq!(asn("BASN12 Vertrag", AsnSyntax::Prefix), None);
+        assert_eq!(asn("Vertrag #12abc", AsnSyntax::Hashtag), None);
+        assert_eq!(asn("Vertrag #99999999999", AsnSyntax::Hashtag), None);
+    }
+
+    fn rule(
+        keywords: Option<&[&str]>,
+        pattern: Option<&str>,
+        document_type: &str,
+    ) -> Result<DocumentTypeRule, config::Error> {
+        let entry = DocumentTypeRuleConfig {
+            keywords: keywords.map(|keywords| keywords.iter().map(|k| k.to_string()).collect()),
+            pattern: pattern.map(str::to_string),
+            document_type: document_type.to_string(),
+            override_existing: None,
+        };
+        DocumentTypeRule::configured(0, &entry)
+    }
+
+    #[test]
+    fn document_type_rules() {
+        let keywords = rule(Some(&["Rechnung", " Kto.Auszug "]), None, "Rechnung").unwrap();
+        assert!(keywords.pattern.is_match("Telekom RECHNUNG März"));
+        assert!(keywords.pattern.is_match("Kto.Auszug 03"));
+        assert!(!keywords.pattern.is_match("KtoXAuszug 03"));
+        assert!(!keywords.pattern.is_match("Rechnungsadresse"));
+        assert!(keywords.override_existing);
+
+        let pattern = rule(None, Some(r"^lohn"), "Lohnabrechnung").unwrap();
+        assert!(pattern.pattern.is_match("Lohnabrechnung März"));
+        assert!(!pattern.pattern.is_match("Abrechnung Lohn"));
+
+        let case_sensitive = rule(None, Some(r"(?-i)KFZ"), "Versicherung").unwrap();
+        assert!(case_sensitive.pattern.is_match("KFZ Versicherung"));
+        assert!(!case_sensitive.pattern.is_match("kfz Versicherung"));
+
+        let entry = DocumentTypeRuleConfig {
+            keywords: Some(vec!["Vertrag".to_string()]),
+            pattern: None,
+            document_type: "Vertrag".to_string(),
+            override_existing: Some(false),
+        };
+        assert!(
+            !DocumentTypeRule::configured(0, &entry)
+                .unwrap()
+                .override_existing
+        );
+    }
+
+    #[test]
+    fn invalid_document_type_rules() {
+        for result in [
+            rule(None, None, "Rechnung"),
+            rule(Some(&[]), None, "Rechnung"),
+            rule(Some(&["Rechnung"]), Some("Rechnung"), "Rechnung"),
+            rule(None, Some("(Rechnung"), "Rechnung"),
+        ] {
+            assert!(
+                matches!(&result, Err(config::Error::Invalid { setting, .. }) if setting == "document type rule #1"),
+                "expected an invalid rule, got {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn first_matching_document_type_rule() {
+        let rules = [
+            rule(Some(&["Rechnung"]), None, "Rechnung").unwrap(),
+            rule(None, Some("rechnung|quittung"), "Beleg").unwrap(),
+        ];
+        let find =
+            |title| find_document_type(title, &rules).map(|rule| rule.document_type.as_str());
+
+        assert_eq!(find("Telekom Rechnung"), Some("Rechnung"));
+        assert_eq!(find("Quittung Bäcker"), Some("Beleg"));
+        assert_eq!(find("Stromrechnung"), Some("Beleg"));
+        assert_eq!(find("Kontoauszug"), None);
+    }
+
+    #[test]
+    fn name_matching() {
+        let names = ["Telekom", "Stadtwerke München", "ACME"];
+        let find = |matching: NameMatching, name| matching.find(name, &names, |name| name).copied();
+
+        assert_eq!(find(NameMatching::Exact, "Telekom"), Some("Telekom"));
+        assert_eq!(find(NameMatching::Exact, "telekom"), None);
+        assert_eq!(find(NameMatching::IgnoreCase, "telekom"), Some("Telekom"));
+        assert_eq!(find(NameMatching::IgnoreCase, "Telecom"), None);
+        assert_eq!(find(NameMatching::Fuzzy, "Telecom"), Some("Telekom"));
+        assert_eq!(
+            find(NameMatching::Fuzzy, "Stadtwerke Munchen"),
+            Some("Stadtwerke München")
+        );
+        assert_eq!(find(NameMatching::Fuzzy, "A.C.M.E."), Some("ACME"));
+        assert_eq!(find(NameMatching::Fuzzy, "Vodafone"), None);
+    }
+
+    #[test]
+    fn ambiguous_names_match_nothing() {
+        fn candidates<'a>(matching: NameMatching, name: &str, names: &[&'a str]) -> Vec<&'a str> {
+            matching
+                .candidates(name, names, |name| name)
+                .into_iter()
+                .copied()
+                .collect()
+        }
+
+        let names = ["Deutsche Bank", "Deutsche Post", "Telekom"];
+        assert_eq!(
+            candidates(NameMatching::Fuzzy, "Deutsche", &names),
+            ["Deutsche Bank", "Deutsche Post"]
+        );
+        assert_eq!(
+            NameMatching::Fuzzy.find("Deutsche", &names, |name| name),
+            None
+        );
+        assert_eq!(
+            NameMatching::Fuzzy.find("Deutsche Post", &names, |name| name),
+            Some(&"Deutsche Post")
+        );
+
+        let names = ["Steuer", "STEUER"];
+        assert_eq!(
+            candidates(NameMatching::IgnoreCase, "steuer", &names),
+            ["Steuer", "STEUER"]
+        );
+        assert_eq!(
+            NameMatching::IgnoreCase.find("steuer", &names, |name| name),
+            None
+        );
+    }
+
+    #[test]
+    fn fuzzy_matching_of_leading_words() {
+        let names = ["Telekom AG", "Stadtwerke München"];
+        let find = |name| NameMatching::Fuzzy.find(name, &names, |name| name).copied();
+
+        assert_eq!(find("Telekom"), Some("Telekom AG"));
+        assert_eq!(find("Telecom"), Some("Telekom AG"));
+        assert_eq!(find("Telekom AG Bonn"), Some("Telekom AG"));
+        assert_eq!(find("Stadtwerke"), Some("Stadtwerke München"));
+        assert_eq!(find("AG"), None);
+        assert_eq!(find("München"), None);
+
+        let names = ["Telekom AG", "Telekom"];
+        assert_eq!(
+            NameMatching::Fuzzy.find("Telekom", &names, |name| name),
+            Some(&"Telekom")
+        );
+    }
+}
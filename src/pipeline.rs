@@ -0,0 +1,566 @@
+//! rewriting documents: fetching them, stripping the date and further metadata from the title
+//! and updating them
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+
+use log::{error, info, warn};
+use tokio::sync::Mutex;
+
+use crate::config::{self, Config};
+use crate::metadata::{
+    find_document_type, find_tags, strip_tags, take_asn, take_segment, DocumentTypeRule,
+    MetadataSettings, NameMatching,
+};
+use crate::paperless::{
+    Client, CustomFieldInstance, DocumentProperties, Error, NamedObject, ObjectKind,
+};
+use crate::title::{rewrite_title, DateSettings, InvalidDate};
+
+/// what happened to a processed document
+#[derive(Debug)]
+pub enum ProcessOutcome {
+    /// the document was updated (or would have been in a dry run)
+    Updated,
+    /// the title contains neither a date nor other metadata
+    Unchanged,
+    /// the title contains something that looks like a date but is not valid
+    InvalidDate(InvalidDate),
+}
+
+/// everything needed to fetch, rewrite and update documents
+pub struct Processor {
+    pub client: Client,
+    pub date_settings: DateSettings,
+    pub metadata_settings: MetadataSettings,
+    /// custom fields receiving the first and last day of date ranges
+    pub range_start_field: Option<i32>,
+    pub range_end_field: Option<i32>,
+    /// dry run performs everything but the final update of the document
+    pub dry_run: bool,
+    /// correspondents and other objects of paperless, fetched when first needed
+    objects: Mutex<HashMap<ObjectKind, Vec<NamedObject>>>,
+}
+
+/// result of looking up a name taken from a title
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    /// id of the existing or newly created object
+    Found(i32),
+    /// the object would have been created if this was no dry run
+    DryRun,
+    /// the name was not looked up but is taken as if the object existed
+    Assumed,
+    Missing,
+    /// several objects match the name equally well, so none is taken
+    Ambiguous,
+}
+
+/// looks up names taken from a title, see [`take_metadata`]
+pub trait Resolve {
+    /// looks up an object by name, creating it if missing and `create` is set
+    fn resolve(
+        &self,
+        kind: ObjectKind,
+        name: &str,
+        matching: NameMatching,
+        create: bool,
+    ) -> impl Future<Output = Result<Lookup, Error>>;
+}
+
+/// metadata taken from a title by [`take_metadata`]
+#[derive(Debug)]
+pub struct TitleMetadata<'a> {
+    /// the title without the metadata taken from it
+    pub title: String,
+    pub archive_serial_number: Option<u32>,
+    /// the first rule matching the title, its document type is not looked up yet
+    pub document_type_rule: Option<&'a DocumentTypeRule>,
+    /// names of the tags stripped from the title, along with their lookups
+    pub tags: Vec<(String, Lookup)>,
+    /// name of the correspondent stripped from the title, along with its lookup
+    pub correspondent: Option<(String, Lookup)>,
+}
+
+/// takes the archive serial number, tags and correspondent from a title (with the date already
+/// stripped) and finds the rule for its document type
+///
+/// names are stripped from the title only if `resolver` does not report them missing. the
+/// correspondent is only taken if a date was stripped, as the segments of other titles need not
+/// follow the naming scheme.
+pub async fn take_metadata<'a>(
+    mut title: String,
+    date_stripped: bool,
+    settings: &'a MetadataSettings,
+    resolver: &impl Resolve,
+) -> Result<TitleMetadata<'a>, Error> {
+    // the archive serial number comes first, so "#00042" is not taken for a tag
+    let mut archive_serial_number = None;
+    if let Some(syntax) = settings.asn {
+        if let Some((asn, remaining_title)) = take_asn(&title, syntax) {
+            archive_serial_number = Some(asn);
+            title = remaining_title;
+        }
+    }
+
+    let document_type_rule = find_document_type(&title, &settings.document_type_rules);
+
+    // tags come first, so they do not end up in the segment naming the correspondent
+    let mut tags = Vec::new();
+    if let Some(tag_settings) = &settings.tags {
+        for name in find_tags(&title, tag_settings.syntax) {
+            let kind = ObjectKind::Tag;
+            match resolver
+                .resolve(kind, &name, tag_settings.matching, tag_settings.create)
+                .await?
+            {
+                Lookup::Missing => info!("no {kind} matches '{name}' - keeping it in the title"),
+                Lookup::Ambiguous => info!("keeping '{name}' in the title"),
+                lookup => tags.push((name, lookup)),
+            }
+        }
+
+        let names: Vec<String> = tags.iter().map(|(name, _)| name.clone()).collect();
+        if !names.is_empty() {
+            title = strip_tags(&title, tag_settings.syntax, &names);
+        }
+    }
+
+    let mut correspondent = None;
+    if let Some(correspondent_settings) = settings.correspondent.filter(|_| date_stripped) {
+        if let Some((name, remaining_title)) = take_segment(&title, correspondent_settings.segment)
+        {
+            let kind = ObjectKind::Correspondent;
+            match resolver
+                .resolve(
+                    kind,
+                    &name,
+                    correspondent_settings.matching,
+                    correspondent_settings.create,
+                )
+                .await?
+            {
+                Lookup::Missing => info!("no {kind} matches '{name}' - keeping it in the title"),
+                Lookup::Ambiguous => info!("keeping '{name}' in the title"),
+                lookup => {
+                    correspondent = Some((name, lookup));
+                    title = remaining_title;
+                }
+            }
+        }
+    }
+
+    Ok(TitleMetadata {
+        title,
+        archive_serial_number,
+        document_type_rule,
+        tags,
+        correspondent,
+    })
+}
+
+impl Processor {
+    pub fn from_env(
+        config: &Config,
+        date_settings: DateSettings,
+        metadata_settings: MetadataSettings,
+        dry_run: bool,
+    ) -> Result<Processor, config::Error> {
+        Ok(Processor {
+            client: Client::from_env(config)?,
+            date_settings,
+            metadata_settings,
+            range_start_field: config::parse_env("PAPERLESS_RANGE_START_FIELD_ID")?,
+            range_end_field: config::parse_env("PAPERLESS_RANGE_END_FIELD_ID")?,
+            dry_run,
+            objects: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// fetches a document and processes it
+    pub async fn process_document_id(&self, document_id: i32) -> Result<ProcessOutcome, Error> {
+        self.client.start_deadline();
+        let document_data = self.client.get_document(document_id).await?;
+        self.rewrite_document(document_data).await
+    }
+
+    /// strips the date and further metadata from the title of the document and updates it
+    /// accordingly
+    ///
+    /// all requests for the document share one retry deadline.
+    pub async fn process_document(
+        &self,
+        document_data: DocumentProperties,
+    ) -> Result<ProcessOutcome, Error> {
+        self.client.start_deadline();
+        self.rewrite_document(document_data).await
+    }
+
+    async fn rewrite_document(
+        &self,
+        document_data: DocumentProperties,
+    ) -> Result<ProcessOutcome, Error> {
+        let document_id = document_data.id;
+
+        info!(
+            "document properties for document {document_id}: {:#?}",
+            document_data
+        );
+
+        let rewrite = match rewrite_title(&document_data.title, &self.date_settings) {
+            Ok(rewrite) => rewrite,
+            Err(invalid) => {
+                info!("{invalid} - leaving document untouched");
+                return Ok(ProcessOutcome::InvalidDate(invalid));
+            }
+        };
+
+        let date_stripped = rewrite.is_some();
+        let (title, created_date, range) = match rewrite {
+            Some(rewrite) => {
+                info!(
+                    "found date '{}' using pattern '{}'",
+                    rewrite.date_text, rewrite.pattern
+                );
+                (rewrite.title, rewrite.created_date, rewrite.range)
+            }
+            None => {
+                info!("no date match found");
+                (document_data.title.clone(), None, None)
+            }
+        };
+
+        let metadata = take_metadata(title, date_stripped, &self.metadata_settings, self).await?;
+        let title = metadata.title;
+
+        let mut archive_serial_number = None;
+        if let Some(asn) = metadata.archive_serial_number {
+            info!("found archive serial number {asn}");
+            if document_data.archive_serial_number != Some(asn) {
+                archive_serial_number = Some(asn);
+            }
+        }
+
+        let document_type = self
+            .document_type(metadata.document_type_rule, document_data.document_type)
+            .await?;
+
+        // paperless replaces all tags of a document, so the existing ones are kept
+        let existing_tags = document_data.tags.as_deref().unwrap_or_default();
+        let mut tags = existing_tags.to_vec();
+        for (_, lookup) in &metadata.tags {
+            if let Lookup::Found(id) = *lookup {
+                if !tags.contains(&id) {
+                    tags.push(id);
+                }
+            }
+        }
+        let tags = (tags.len() > existing_tags.len()).then_some(tags);
+
+        let correspondent = match metadata.correspondent {
+            Some((_, Lookup::Found(id))) => Some(id),
+            _ => None,
+        };
+
+        // paperless replaces all custom fields of a document, so the existing ones are kept
+        let range_fields = [self.range_start_field, self.range_end_field];
+        let custom_fields = match range {
+            Some((first_day, last_day)) if range_fields.iter().any(Option::is_some) => {
+                let mut custom_fields = document_data.custom_fields.clone().unwrap_or_default();
+                for (field, date) in range_fields.into_iter().zip([first_day, last_day]) {
+                    let Some(field) = field else {
+                        continue;
+                    };
+                    custom_fields.retain(|instance| instance.field != field);
+                    custom_fields.push(CustomFieldInstance {
+                        field,
+                        value: date.format("%Y-%m-%d").to_string().into(),
+                    });
+                }
+                Some(custom_fields)
+            }
+            _ => None,
+        };
+
+        if title == document_data.title
+            && correspondent.is_none()
+            && tags.is_none()
+            && document_type.is_none()
+            && archive_serial_number.is_none()
+            && created_date.is_none()
+            && custom_fields.is_none()
+        {
+            info!("nothing to do");
+            return Ok(ProcessOutcome::Unchanged);
+        }
+
+        // contruct new document properties
+        let new_document_data = DocumentProperties {
+            id: document_id,
+            title,
+            created_date: created_date.map(|date| date.format("%Y-%m-%d").to_string()),
+            correspondent,
+            tags,
+            document_type,
+            archive_serial_number,
+            custom_fields,
+        };
+
+        info!(
+            "new document properties for document {document_id}: {:#?}",
+            new_document_data
+        );
+
+        if self.dry_run {
+            info!(
+                "dry run - would send PATCH {} with body:\n{}",
+                self.client.document_url(document_id),
+                serde_json::to_string_pretty(&new_document_data)
+                    .expect("unable to serialize document properties")
+            );
+            return Ok(ProcessOutcome::Updated);
+        }
+
+        self.client
+            .patch_document(document_id, &new_document_data)
+            .await?;
+        info!("successfully updated document");
+
+        Ok(ProcessOutcome::Updated)
+    }
+
+    /// the id of the document type of the rule matching the title, unless the rule keeps the
+    /// document type the document already has
+    async fn document_type(
+        &self,
+        rule: Option<&DocumentTypeRule>,
+        existing: Option<i32>,
+    ) -> Result<Option<i32>, Error> {
+        let Some(rule) = rule else {
+            return Ok(None);
+        };
+
+        if existing.is_some() && !rule.override_existing {
+            info!(
+                "title matches document type '{}' - keeping the document type of the document",
+                rule.document_type
+            );
+            return Ok(None);
+        }
+
+        let kind = ObjectKind::DocumentType;
+        match self
+            .lookup(kind, &rule.document_type, NameMatching::IgnoreCase, false)
+            .await?
+        {
+            Lookup::Found(id) if Some(id) == existing => Ok(None),
+            Lookup::Found(id) => Ok(Some(id)),
+            Lookup::Ambiguous => Ok(None),
+            Lookup::DryRun | Lookup::Assumed | Lookup::Missing => {
+                warn!(
+                    "{kind} '{}' of the matching rule does not exist in paperless",
+                    rule.document_type
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// looks up an object by a name taken from a title, creating it if missing and allowed
+    async fn lookup(
+        &self,
+        kind: ObjectKind,
+        name: &str,
+        matching: NameMatching,
+        create: bool,
+    ) -> Result<Lookup, Error> {
+        let mut objects = self.objects.lock().await;
+
+        let objects = match objects.get_mut(&kind) {
+            Some(objects) => objects,
+            None => {
+                let fetched = self.client.list_objects(kind).await?;
+                objects.entry(kind).or_insert(fetched)
+            }
+        };
+
+        match matching.candidates(name, objects, |object| &object.name)[..] {
+            [] => (),
+            [object] => {
+                info!(
+                    "found {kind} '{}' ({}) for '{name}'",
+                    object.name, object.id
+                );
+                return Ok(Lookup::Found(object.id));
+            }
+            ref candidates => {
+                let names: Vec<&str> = candidates
+                    .iter()
+                    .map(|object| object.name.as_str())
+                    .collect();
+                warn!(
+                    "'{name}' matches the {kind}s {} equally well - taking none of them",
+                    names.join(", ")
+                );
+                return Ok(Lookup::Ambiguous);
+            }
+        }
+
+        if !create {
+            return Ok(Lookup::Missing);
+        }
+
+        if self.dry_run {
+            info!("dry run - would create {kind} '{name}'");
+            return Ok(Lookup::DryRun);
+        }
+
+        let object = self.client.create_object(kind, name).await?;
+        info!("created {kind} '{}' ({})", object.name, object.id);
+
+        let id = object.id;
+        objects.push(object);
+        Ok(Lookup::Found(id))
+    }
+}
+
+impl Resolve for Processor {
+    fn resolve(
+        &self,
+        kind: ObjectKind,
+        name: &str,
+        matching: NameMatching,
+        create: bool,
+    ) -> impl Future<Output = Result<Lookup, Error>> {
+        self.lookup(kind, name, matching, create)
+    }
+}
+
+/// counts the outcomes of processing multiple documents
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub changed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl Summary {
+    pub fn record(&mut self, document_id: i32, result: Result<ProcessOutcome, Error>) {
+        match result {
+            Ok(ProcessOutcome::Updated) => self.changed += 1,
+            Ok(ProcessOutcome::Unchanged | ProcessOutcome::InvalidDate(_)) => self.skipped += 1,
+            Err(err) => {
+                error!("processing document {document_id} failed: {err}");
+                self.failed += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} changed, {} skipped, {} failed",
+            self.changed, self.skipped, self.failed
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::{AsnSyntax, CorrespondentSettings, Segment, TagSettings, TagSyntax};
+
+    /// knows a fixed set of objects and never creates any
+    struct Known(Vec<(ObjectKind, &'static str, i32)>);
+
+    impl Resolve for Known {
+        async fn resolve(
+            &self,
+            kind: ObjectKind,
+            name: &str,
+            matching: NameMatching,
+            _create: bool,
+        ) -> Result<Lookup, Error> {
+            let objects: Vec<_> = self.0.iter().filter(|object| object.0 == kind).collect();
+            Ok(match matching.find(name, &objects, |object| object.1) {
+                Some(object) => Lookup::Found(object.2),
+                None => Lookup::Missing,
+            })
+        }
+    }
+
+    fn settings() -> MetadataSettings {
+        MetadataSettings {
+            correspondent: Some(CorrespondentSettings {
+                segment: Segment::First,
+                matching: NameMatching::IgnoreCase,
+                create: false,
+            }),
+            tags: Some(TagSettings {
+                syntax: TagSyntax::Hashtag,
+                matching: NameMatching::IgnoreCase,
+                create: false,
+            }),
+            document_type_rules: Vec::new(),
+            asn: Some(AsnSyntax::Hashtag),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_resolved_names_are_taken() {
+        let settings = settings();
+        let resolver = Known(vec![
+            (ObjectKind::Tag, "Steuer", 3),
+            (ObjectKind::Correspondent, "Telekom", 7),
+        ]);
+        let title = "Telekom - Rechnung #steuer #unbekannt #00042".to_string();
+
+        let metadata = take_metadata(title, true, &settings, &resolver)
+            .await
+            .unwrap();
+
+        assert_eq!(metadata.title, "Rechnung #unbekannt");
+        assert_eq!(metadata.archive_serial_number, Some(42));
+        assert_eq!(metadata.tags, [("steuer".to_string(), Lookup::Found(3))]);
+        assert_eq!(
+            metadata.correspondent,
+            Some(("Telekom".to_string(), Lookup::Found(7)))
+        );
+    }
+
+    #[tokio::test]
+    async fn correspondents_are_only_taken_from_dated_titles() {
+        let settings = settings();
+        let resolver = Known(vec![
+            (ObjectKind::Tag, "Steuer", 3),
+            (ObjectKind::Correspondent, "Telekom", 7),
+        ]);
+        let title = "Telekom - Rechnung #steuer".to_string();
+
+        let metadata = take_metadata(title, false, &settings, &resolver)
+            .await
+            .unwrap();
+
+        assert_eq!(metadata.title, "Telekom - Rechnung");
+        assert_eq!(metadata.tags, [("steuer".to_string(), Lookup::Found(3))]);
+        assert_eq!(metadata.correspondent, None);
+    }
+
+    #[tokio::test]
+    async fn unknown_correspondents_stay_in_the_title() {
+        let settings = settings();
+        let resolver = Known(Vec::new());
+        let title = "Stadtwerke - Abschlag #strom".to_string();
+
+        let metadata = take_metadata(title, true, &settings, &resolver)
+            .await
+            .unwrap();
+
+        assert_eq!(metadata.title, "Stadtwerke - Abschlag #strom");
+        assert!(metadata.tags.is_empty());
+        assert_eq!(metadata.correspondent, None);
+    }
+}
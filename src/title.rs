@@ -0,0 +1,1245 @@
+//! finding dates in document titles and stripping them
+
+use std::env;
+use std::fmt;
+use std::ops::Range;
+
+use chrono::{Datelike, Days, Months, NaiveDate, Weekday};
+use regex::Regex;
+
+use crate::config::{self, Config, DatePatternConfig};
+
+/// earliest year accepted for a title date unless configured otherwise
+const MIN_YEAR_DEFAULT: i32 = 1900;
+
+/// two-digit years from this value on belong to the 1900s, smaller ones to the 2000s
+const CENTURY_PIVOT_DEFAULT: i32 = 70;
+
+/// order of day and month in ambiguous slash separated dates like 03/04/2024
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateOrder {
+    DayMonthYear,
+    MonthDayYear,
+}
+
+impl DateOrder {
+    /// interprets a paperless style date order (DMY, MDY, YMD, ...) - only the relative
+    /// position of day and month matters here
+    pub fn from_setting(setting: &str) -> DateOrder {
+        let setting = setting.to_uppercase();
+        match (setting.find('D'), setting.find('M')) {
+            (Some(day), Some(month)) if month < day => DateOrder::MonthDayYear,
+            _ => DateOrder::DayMonthYear,
+        }
+    }
+}
+
+/// where in the title a date is searched
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DatePosition {
+    /// only a date at the very beginning of the title
+    Start,
+    /// the first date anywhere in the title
+    First,
+    /// the last date anywhere in the title
+    Last,
+}
+
+/// how dates lacking the day (2024-03, 2024-Q1, KW12 2024) or the month (2023) are handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PartialDates {
+    /// partial dates are not recognized
+    Off,
+    /// the created date is set to the first day of the period
+    Start,
+    /// the created date is set to the last day of the period
+    End,
+    /// the date is only stripped from the title, the created date is left unchanged
+    Keep,
+}
+
+/// which end of a date range becomes the created date
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RangeDate {
+    Start,
+    End,
+}
+
+/// a named regex used to find dates in titles
+///
+/// the regex must provide the named capture `year` and may provide `month` or `monthname` and
+/// `day`, `quarter` or `week`. matches without `day` are partial dates.
+#[derive(Debug, Clone)]
+pub struct DatePattern {
+    pub name: String,
+    regex: Regex,
+    /// whether matches are dates for sure, so an invalid one is reported instead of ignored
+    ///
+    /// plain numbers like 12345678 or 1000 may be anything, as may be dates with two-digit years
+    /// like 12-34-56, so these are never reported.
+    strict: bool,
+}
+
+impl DatePattern {
+    /// compiles a user supplied pattern, anchoring it at the position where a date is searched
+    pub fn new(name: &str, pattern: &str) -> Result<DatePattern, String> {
+        let regex = Regex::new(&format!("^(?:{pattern})")).map_err(|err| err.to_string())?;
+
+        let groups: Vec<&str> = regex.capture_names().flatten().collect();
+        let has_month = groups.contains(&"month") || groups.contains(&"monthname");
+
+        if !groups.contains(&"year") {
+            return Err("named capture group year missing".to_string());
+        }
+
+        if groups.contains(&"day") && !has_month {
+            return Err("named capture group month or monthname missing".to_string());
+        }
+
+        Ok(DatePattern {
+            name: name.to_string(),
+            regex,
+            strict: true,
+        })
+    }
+
+    /// looks up a built-in pattern by its name
+    pub fn builtin(name: &str) -> Option<DatePattern> {
+        DATE_PATTERNS
+            .iter()
+            .chain(SLASH_DATE_PATTERNS.iter().map(|(pattern, _)| pattern))
+            .chain(PARTIAL_DATE_PATTERNS.iter())
+            .find(|pattern| pattern.name == name)
+            .cloned()
+    }
+
+    /// all built-in patterns, with the ambiguous slash separated dates in the preferred order
+    pub fn builtins(date_order: DateOrder) -> Vec<DatePattern> {
+        let preferred = SLASH_DATE_PATTERNS
+            .iter()
+            .filter(|(_, order)| *order == date_order);
+        let fallback = SLASH_DATE_PATTERNS
+            .iter()
+            .filter(|(_, order)| *order != date_order);
+
+        DATE_PATTERNS
+            .iter()
+            .chain(preferred.chain(fallback).map(|(pattern, _)| pattern))
+            .chain(PARTIAL_DATE_PATTERNS.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+/// settings controlling how dates in titles are interpreted
+#[derive(Debug)]
+pub struct DateSettings {
+    /// date patterns in the order they are tried
+    pub patterns: Vec<DatePattern>,
+    pub position: DatePosition,
+    pub partial_dates: PartialDates,
+    pub range_date: RangeDate,
+    /// plausible year window, dates outside of it are rejected
+    pub min_year: i32,
+    pub max_year: i32,
+    /// two-digit years >= pivot are expanded to 19xx, smaller ones to 20xx
+    pub century_pivot: i32,
+}
+
+impl DateSettings {
+    pub fn load(config: &Config) -> Result<DateSettings, config::Error> {
+        let date_order = match env::var("PAPERLESS_DATE_ORDER") {
+            Ok(setting) => DateOrder::from_setting(&setting),
+            Err(_) => DateOrder::DayMonthYear,
+        };
+
+        let position = match env::var("PAPERLESS_DATE_POSITION").as_deref() {
+            Ok("start") | Err(_) => DatePosition::Start,
+            Ok("first") => DatePosition::First,
+            Ok("last") => DatePosition::Last,
+            Ok(position) => {
+                return Err(config::Error::invalid(
+                    "PAPERLESS_DATE_POSITION",
+                    format!("unknown value '{position}', expected start, first or last"),
+                ))
+            }
+        };
+
+        let partial_dates = match env::var("PAPERLESS_PARTIAL_DATES").as_deref() {
+            Ok("off") | Err(_) => PartialDates::Off,
+            Ok("start") => PartialDates::Start,
+            Ok("end") => PartialDates::End,
+            Ok("keep") => PartialDates::Keep,
+            Ok(partial_dates) => {
+                return Err(config::Error::invalid(
+                    "PAPERLESS_PARTIAL_DATES",
+                    format!("unknown value '{partial_dates}', expected off, start, end or keep"),
+                ))
+            }
+        };
+
+        let range_date = match env::var("PAPERLESS_RANGE_DATE").as_deref() {
+            Ok("start") | Err(_) => RangeDate::Start,
+            Ok("end") => RangeDate::End,
+            Ok(range_date) => {
+                return Err(config::Error::invalid(
+                    "PAPERLESS_RANGE_DATE",
+                    format!("unknown value '{range_date}', expected start or end"),
+                ))
+            }
+        };
+
+        let min_year = config::parse_env("PAPERLESS_DATE_MIN_YEAR")?.unwrap_or(MIN_YEAR_DEFAULT);
+
+        // documents from the future are unlikely, but allow for the turn of the year
+        let max_year = config::parse_env("PAPERLESS_DATE_MAX_YEAR")?
+            .unwrap_or_else(|| chrono::Local::now().year() + 1);
+
+        let century_pivot =
+            config::parse_env("PAPERLESS_DATE_CENTURY_PIVOT")?.unwrap_or(CENTURY_PIVOT_DEFAULT);
+        if !(0..=100).contains(&century_pivot) {
+            return Err(config::Error::invalid(
+                "PAPERLESS_DATE_CENTURY_PIVOT",
+                format!("{century_pivot} is not between 0 and 100"),
+            ));
+        }
+
+        let patterns = match &config.date_patterns {
+            Some(patterns) => patterns
+                .iter()
+                .enumerate()
+                .map(|(index, entry)| DateSettings::configured_pattern(index, entry))
+                .collect::<Result<_, _>>()?,
+            None => DatePattern::builtins(date_order),
+        };
+
+        Ok(DateSettings {
+            patterns,
+            position,
+            partial_dates,
+            range_date,
+            min_year,
+            max_year,
+            century_pivot,
+        })
+    }
+
+    /// turns a date pattern entry of the configuration file into a pattern, rejecting invalid
+    /// entries so broken configurations are noticed right away
+    fn configured_pattern(
+        index: usize,
+        entry: &DatePatternConfig,
+    ) -> Result<DatePattern, config::Error> {
+        let setting = format!("date pattern #{}", index + 1);
+
+        match (&entry.builtin, &entry.pattern) {
+            (Some(builtin), None) => DatePattern::builtin(builtin).ok_or_else(|| {
+                config::Error::invalid(&setting, format!("unknown built-in pattern '{builtin}'"))
+            }),
+            (None, Some(pattern)) => {
+                let name = entry
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("custom #{}", index + 1));
+                DatePattern::new(&name, pattern)
+                    .map_err(|err| config::Error::invalid(&format!("date pattern '{name}'"), err))
+            }
+            _ => Err(config::Error::invalid(
+                &setting,
+                "exactly one of pattern or builtin must be set",
+            )),
+        }
+    }
+
+    /// expands a two-digit year to a full year using the century pivot
+    fn expand_year(&self, year: i32) -> i32 {
+        if year >= self.century_pivot {
+            1900 + year
+        } else {
+            2000 + year
+        }
+    }
+}
+
+/// a date or date range found in a document title
+#[derive(Debug)]
+pub struct TitleDate {
+    /// first and last day of the period described by the (first) date, the same day for complete
+    /// dates
+    pub period: (NaiveDate, NaiveDate),
+    /// period described by the second date if the title contains a date range
+    pub range_end: Option<(NaiveDate, NaiveDate)>,
+    /// whether the date lacks the day or month
+    pub partial: bool,
+    /// name of the pattern that matched
+    pub pattern: String,
+    /// byte range of the title covered by the date and its trailing separator
+    pub start: usize,
+    pub end: usize,
+}
+
+impl TitleDate {
+    /// the date to set as created date, if any
+    pub fn created_date(&self, settings: &DateSettings) -> Option<NaiveDate> {
+        let (first_day, last_day) = match (self.range_end, settings.range_date) {
+            (Some(range_end), RangeDate::End) => range_end,
+            _ => self.period,
+        };
+
+        if !self.partial {
+            return Some(first_day);
+        }
+
+        match settings.partial_dates {
+            PartialDates::End => Some(last_day),
+            PartialDates::Keep => None,
+            _ => Some(first_day),
+        }
+    }
+
+    /// first and last day of the date range, if the title contains one
+    pub fn range(&self) -> Option<(NaiveDate, NaiveDate)> {
+        self.range_end
+            .map(|(_, last_day)| (self.period.0, last_day))
+    }
+}
+
+/// creates a built-in pattern, these are anchored and known to be valid
+fn builtin_pattern(name: &str, pattern: &str) -> DatePattern {
+    DatePattern {
+        name: name.to_string(),
+        regex: Regex::new(pattern).unwrap(),
+        strict: true,
+    }
+}
+
+/// creates a built-in pattern matching plain numbers, which are ignored unless a valid date
+fn numeric_pattern(name: &str, pattern: &str) -> DatePattern {
+    DatePattern {
+        strict: false,
+        ..builtin_pattern(name, pattern)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref DATE_PATTERNS: [DatePattern; 6] = [
+        // match title for ISO date, optionally with two-digit year
+        builtin_pattern("iso", r"^(?<year>[0-9]{4}|[0-9]{2})-(?<month>[0-9]{2})-(?<day>[0-9]{2})\b\s*-?\s*"),
+        // match title for German, optionally with two-digit year
+        builtin_pattern("german", r"^(?<day>[0-9]{2})\.(?<month>[0-9]{2})\.(?<year>[0-9]{4}|[0-9]{2})\b\s*-?\s*"),
+        // match title for slash separated date starting with the year
+        builtin_pattern("year-slash", r"^(?<year>[0-9]{4})/(?<month>[0-9]{1,2})/(?<day>[0-9]{1,2})\s*-?\s*"),
+        // match title for compact date with optional time as used by scanners and phones
+        // (20240315, 20240315_143002, 20240315T143002), numbers that are no time are left alone
+        numeric_pattern("compact", r"^(?<year>[0-9]{4})(?<month>[0-9]{2})(?<day>[0-9]{2})(?:[_T-]?(?<hour>[01][0-9]|2[0-3])(?<minute>[0-5][0-9])(?<second>[0-5][0-9]))?(?:[\s_-]+|$)"),
+        // match title for date with month name and leading day (15 March 2024, 15. März 2024, 15th Mar 2024)
+        builtin_pattern("day-monthname", r"^(?<day>[0-9]{1,2})(?:st|nd|rd|th)?\.?\s*(?<monthname>\p{L}+)\.?,?\s+(?<year>[0-9]{4})\s*-?\s*"),
+        // match title for date with leading month name (March 15, 2024, Mar 15th 2024)
+        builtin_pattern("monthname-day", r"^(?<monthname>\p{L}+)\.?\s+(?<day>[0-9]{1,2})(?:st|nd|rd|th)?\.?,?\s+(?<year>[0-9]{4})\s*-?\s*"),
+    ];
+
+    // slash separated dates with the year at the end are ambiguous, both variants are tried
+    // with the preferred date order first
+    static ref SLASH_DATE_PATTERNS: [(DatePattern, DateOrder); 2] = [
+        // match title for US date
+        (
+            builtin_pattern("us-slash", r"^(?<month>[0-9]{1,2})/(?<day>[0-9]{1,2})/(?<year>[0-9]{4}|[0-9]{2})\b\s*-?\s*"),
+            DateOrder::MonthDayYear,
+        ),
+        // match title for British/European slash separated date
+        (
+            builtin_pattern("european-slash", r"^(?<day>[0-9]{1,2})/(?<month>[0-9]{1,2})/(?<year>[0-9]{4}|[0-9]{2})\b\s*-?\s*"),
+            DateOrder::DayMonthYear,
+        ),
+    ];
+
+    // partial dates may not be followed by another part of a date, e.g. 2024-03 must not be
+    // taken from 2024-03-45
+    static ref PARTIAL_DATE_PATTERNS: [DatePattern; 9] = [
+        // match title for year and month (2024-03, 2024/03)
+        builtin_pattern("year-month", r"^(?<year>[0-9]{4})[-/](?<month>[0-9]{2})(?:\s*-\s+|_|\s+|$)"),
+        // match title for month and year (03.2024, 03/2024)
+        builtin_pattern("month-year", r"^(?<month>[0-9]{2})[./](?<year>[0-9]{4})(?:\s*-\s+|_|\s+|$)"),
+        // match title for month name and year (March 2024, März 2024)
+        builtin_pattern("monthname-year", r"^(?<monthname>\p{L}+)\.?\s+(?<year>[0-9]{4})(?:\s*-\s+|_|\s+|$)"),
+        // match title for year and quarter (2024-Q1, 2024 Q1)
+        builtin_pattern("year-quarter", r"^(?<year>[0-9]{4})[-_ ]?[Qq](?<quarter>[1-4])(?:\s*-\s+|_|\s+|$)"),
+        // match title for quarter and year (Q1 2024, Q1/2024)
+        builtin_pattern("quarter-year", r"^[Qq](?<quarter>[1-4])[-/_ ]?(?<year>[0-9]{4})(?:\s*-\s+|_|\s+|$)"),
+        // match title for German quarter and year (1. Quartal 2024)
+        builtin_pattern("quartal-year", r"^(?<quarter>[1-4])\.\s*Quartal\s+(?<year>[0-9]{4})(?:\s*-\s+|_|\s+|$)"),
+        // match title for year and ISO calendar week (2024-W12, 2024 KW12)
+        builtin_pattern("year-week", r"^(?<year>[0-9]{4})[-_ ]?(?i:KW|CW|W)\s*(?<week>[0-9]{1,2})(?:\s*-\s+|_|\s+|$)"),
+        // match title for ISO calendar week and year (KW12 2024, KW 12/2024, CW12-2024, Week 12 2024)
+        builtin_pattern("week-year", r"^(?i:KW|CW|Week|W)\s*(?<week>[0-9]{1,2})(?:\s*[/.-]\s*|\s+)(?<year>[0-9]{4})(?:\s*-\s+|_|\s+|$)"),
+        // match title for year only
+        numeric_pattern("year", r"^(?<year>[0-9]{4})(?:\s*-\s+|_|\s+|$)"),
+    ];
+
+    // separator between the two dates of a range, a dash is already consumed by the first date
+    static ref RANGE_SEPARATOR: Regex = Regex::new(r"^(?i:(?:bis|to|until|till|–|—)\s*)?").unwrap();
+}
+
+/// month names of english and german, full names first followed by common abbreviations
+const MONTH_NAMES: [[&[&str]; 12]; 2] = [
+    [
+        &["january", "jan"],
+        &["february", "feb"],
+        &["march", "mar"],
+        &["april", "apr"],
+        &["may"],
+        &["june", "jun"],
+        &["july", "jul"],
+        &["august", "aug"],
+        &["september", "sep", "sept"],
+        &["october", "oct"],
+        &["november", "nov"],
+        &["december", "dec"],
+    ],
+    [
+        &["januar", "jänner", "jan"],
+        &["februar", "feber", "feb"],
+        &["märz", "maerz", "mär", "mrz"],
+        &["april", "apr"],
+        &["mai"],
+        &["juni", "jun"],
+        &["juli", "jul"],
+        &["august", "aug"],
+        &["september", "sep", "sept"],
+        &["oktober", "okt"],
+        &["november", "nov"],
+        &["dezember", "dez"],
+    ],
+];
+
+/// looks up the month number (1-12) for a full or abbreviated month name in either language
+fn month_from_name(name: &str) -> Option<u32> {
+    let name = name.to_lowercase();
+
+    MONTH_NAMES.iter().find_map(|months| {
+        months
+            .iter()
+            .position(|names| names.contains(&name.as_str()))
+            .map(|index| index as u32 + 1)
+    })
+}
+
+/// a title prefix that matched a date pattern but does not describe a valid date
+#[derive(Debug)]
+pub struct InvalidDate {
+    text: String,
+    reason: String,
+}
+
+impl fmt::Display for InvalidDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "'{}' is not a valid date: {}",
+            trim_separators(&self.text),
+            self.reason
+        )
+    }
+}
+
+/// the period within a year a date in a title describes
+#[derive(Debug, Clone, Copy)]
+enum Period {
+    Year,
+    Quarter(u32),
+    Month(u32),
+    /// ISO calendar week
+    Week(u32),
+    /// month and day of a complete date
+    Day(u32, u32),
+}
+
+/// dashes separating parts of a title, including en and em dashes
+fn is_dash(c: char) -> bool {
+    matches!(c, '-' | '–' | '—')
+}
+
+/// whitespace, dashes and underscores between the parts of a title
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || is_dash(c) || c == '_'
+}
+
+/// removes the separators a date pattern consumed after the date
+fn trim_separators(text: &str) -> &str {
+    text.trim_end_matches(is_separator)
+}
+
+/// checks that the date exists in the calendar and lies within the plausible year window,
+/// returning the first and last day of the period it describes
+fn validate_date(
+    year: i32,
+    period: Period,
+    settings: &DateSettings,
+) -> Result<(NaiveDate, NaiveDate), String> {
+    if !(settings.min_year..=settings.max_year).contains(&year) {
+        return Err(format!(
+            "year {year} is outside of the plausible range {}-{}",
+            settings.min_year, settings.max_year
+        ));
+    }
+
+    // first day of the period and the number of months it spans, if it is made of months
+    let (first_day, months) = match period {
+        Period::Year => (NaiveDate::from_ymd_opt(year, 1, 1), 12),
+        Period::Quarter(quarter) => {
+            if !(1..=4).contains(&quarter) {
+                return Err(format!("quarter {quarter} is out of range"));
+            }
+            (NaiveDate::from_ymd_opt(year, (quarter - 1) * 3 + 1, 1), 3)
+        }
+        Period::Month(month) => {
+            if !(1..=12).contains(&month) {
+                return Err(format!("month {month} is out of range"));
+            }
+            (NaiveDate::from_ymd_opt(year, month, 1), 1)
+        }
+        Period::Week(week) => {
+            let first_day = NaiveDate::from_isoywd_opt(year, week, Weekday::Mon);
+            let last_day = NaiveDate::from_isoywd_opt(year, week, Weekday::Sun);
+            return first_day
+                .zip(last_day)
+                .ok_or_else(|| format!("week {week} does not exist in {year}"));
+        }
+        Period::Day(month, day) => {
+            if !(1..=12).contains(&month) {
+                return Err(format!("month {month} is out of range"));
+            }
+            return match NaiveDate::from_ymd_opt(year, month, day) {
+                Some(date) => Ok((date, date)),
+                None => Err(format!("day {day} does not exist in {year}-{month:02}")),
+            };
+        }
+    };
+
+    let last_day = first_day
+        .and_then(|date| date.checked_add_months(Months::new(months)))
+        .and_then(|date| date.checked_sub_days(Days::new(1)));
+
+    first_day
+        .zip(last_day)
+        .ok_or_else(|| format!("year {year} is out of range"))
+}
+
+/// searches the title for a date according to the configured position
+///
+/// complete dates anywhere in the title take precedence over partial dates. if a pattern matched
+/// at the start of the title but is no valid date, the reason is returned as error. when searching
+/// the whole title, invalid matches are ignored, as any number in the title may look like a date.
+pub fn find_title_date(
+    title: &str,
+    settings: &DateSettings,
+) -> Result<Option<TitleDate>, InvalidDate> {
+    let positions: Vec<usize> = match settings.position {
+        DatePosition::Start => vec![0],
+        DatePosition::First => word_starts(title),
+        DatePosition::Last => word_starts(title).into_iter().rev().collect(),
+    };
+
+    let mut first_invalid = None;
+
+    for partial in [false, true] {
+        // an invalid complete date is not second-guessed by looking for a partial one
+        if partial && (settings.partial_dates == PartialDates::Off || first_invalid.is_some()) {
+            break;
+        }
+
+        for &start in &positions {
+            match find_date_at(title, start, partial, settings) {
+                Ok(Some(title_date)) => {
+                    let title_date = match settings.position {
+                        DatePosition::Last => covering_date(title, title_date, partial, settings),
+                        _ => title_date,
+                    };
+                    return Ok(Some(extend_to_range(title, title_date, settings)));
+                }
+                Ok(None) => (),
+                Err(invalid) if settings.position == DatePosition::Start => {
+                    first_invalid.get_or_insert(invalid);
+                }
+                Err(_) => (),
+            }
+        }
+    }
+
+    match first_invalid {
+        Some(invalid) => Err(invalid),
+        None => Ok(None),
+    }
+}
+
+/// positions in the title where a date may start, which is the beginning of a word
+fn word_starts(title: &str) -> Vec<usize> {
+    let word_starts = title
+        .char_indices()
+        .filter(|(_, c)| !c.is_alphanumeric())
+        .map(|(index, c)| index + c.len_utf8())
+        .filter(|index| *index < title.len());
+
+    std::iter::once(0).chain(word_starts).collect()
+}
+
+/// the longest date covering the date found, if any
+///
+/// searching backwards, the year of a longer date like "März 2024" or "KW 12/2024" is found as a
+/// date of its own first.
+fn covering_date(
+    title: &str,
+    title_date: TitleDate,
+    partial: bool,
+    settings: &DateSettings,
+) -> TitleDate {
+    word_starts(title)
+        .into_iter()
+        .take_while(|start| *start < title_date.start)
+        .find_map(
+            |start| match find_date_at(title, start, partial, settings) {
+                Ok(Some(covering)) if covering.end > title_date.start => Some(covering),
+                _ => None,
+            },
+        )
+        .unwrap_or(title_date)
+}
+
+/// extends a date to a date range if another date of the same kind directly follows or precedes it
+fn extend_to_range(title: &str, title_date: TitleDate, settings: &DateSettings) -> TitleDate {
+    let range_end_start = title_date.end
+        + RANGE_SEPARATOR
+            .find(&title[title_date.end..])
+            .unwrap()
+            .len();
+
+    if let Ok(Some(range_end)) = find_date_at(title, range_end_start, title_date.partial, settings)
+    {
+        if range_end.period.0 >= title_date.period.0 {
+            return TitleDate {
+                range_end: Some(range_end.period),
+                end: range_end.end,
+                ..title_date
+            };
+        }
+    }
+
+    // when searching for the last date, the date found may be the end of a range
+    for start in word_starts(title)
+        .into_iter()
+        .filter(|start| *start < title_date.start)
+    {
+        let Ok(Some(range_start)) = find_date_at(title, start, title_date.partial, settings) else {
+            continue;
+        };
+
+        let separator = RANGE_SEPARATOR.find(&title[range_start.end..]).unwrap();
+        if range_start.end + separator.len() == title_date.start
+            && range_start.period.0 <= title_date.period.0
+        {
+            return TitleDate {
+                range_end: Some(title_date.period),
+                end: title_date.end,
+                ..range_start
+            };
+        }
+    }
+
+    title_date
+}
+
+/// tries all date patterns at the given position of the title, returning the first complete or
+/// partial match that is a valid date
+fn find_date_at(
+    title: &str,
+    start: usize,
+    partial: bool,
+    settings: &DateSettings,
+) -> Result<Option<TitleDate>, InvalidDate> {
+    let mut first_invalid = None;
+
+    for (pattern, date_parts) in settings.patterns.iter().filter_map(|pattern| {
+        pattern
+            .regex
+            .captures(&title[start..])
+            .map(|date_parts| (pattern, date_parts))
+    }) {
+        let Some(year) = date_parts.name("year") else {
+            continue;
+        };
+        let strict = pattern.strict && year.len() == 4;
+        let year = match year.as_str().parse().unwrap_or(0) {
+            parsed if year.len() == 2 => settings.expand_year(parsed),
+            parsed => parsed,
+        };
+
+        // words that are no month names are no dates at all
+        let month = match (date_parts.name("monthname"), date_parts.name("month")) {
+            (Some(name), _) => match month_from_name(name.as_str()) {
+                Some(month) => Some(month),
+                None => continue,
+            },
+            (None, Some(month)) => Some(month.as_str().parse().unwrap_or(0)),
+            (None, None) => None,
+        };
+        let number = |name: &str| {
+            date_parts
+                .name(name)
+                .map(|part| part.as_str().parse().unwrap_or(0))
+        };
+
+        let period = match (month, number("day"), number("quarter"), number("week")) {
+            (Some(month), Some(day), None, None) => Period::Day(month, day),
+            (Some(month), None, None, None) => Period::Month(month),
+            (None, None, Some(quarter), None) => Period::Quarter(quarter),
+            (None, None, None, Some(week)) => Period::Week(week),
+            (None, None, None, None) => Period::Year,
+            // parts that do not make up a date
+            _ => continue,
+        };
+
+        // only the kind of date searched for is considered
+        if partial == matches!(period, Period::Day(..)) {
+            continue;
+        }
+
+        match validate_date(year, period, settings) {
+            Ok(period) => {
+                return Ok(Some(TitleDate {
+                    period,
+                    range_end: None,
+                    partial,
+                    pattern: pattern.name.clone(),
+                    start,
+                    end: start + date_parts[0].len(),
+                }));
+            }
+            Err(reason) if strict => {
+                first_invalid.get_or_insert(InvalidDate {
+                    text: date_parts[0].to_string(),
+                    reason,
+                });
+            }
+            // numbers that happen to look like a date
+            Err(_) => (),
+        }
+    }
+
+    match first_invalid {
+        Some(invalid) => Err(invalid),
+        None => Ok(None),
+    }
+}
+
+/// the result of stripping a date from a title
+#[derive(Debug)]
+pub struct TitleRewrite {
+    /// the title without the date
+    pub title: String,
+    /// the date as written in the title
+    pub date_text: String,
+    /// name of the pattern that matched
+    pub pattern: String,
+    /// the date to set as created date, if any
+    pub created_date: Option<NaiveDate>,
+    /// first and last day of the date range, if the title contains one
+    pub range: Option<(NaiveDate, NaiveDate)>,
+}
+
+/// finds the date in the title and strips it, this is all that happens to a title
+///
+/// the title is kept as it is if nothing but the date and separators is left.
+pub fn rewrite_title(
+    title: &str,
+    settings: &DateSettings,
+) -> Result<Option<TitleRewrite>, InvalidDate> {
+    let Some(title_date) = find_title_date(title, settings)? else {
+        return Ok(None);
+    };
+
+    // a title consisting of nothing but the date, like the timestamp of a scan, is kept
+    let mut new_title = strip_title_date(title, &title_date);
+    if new_title.is_empty() {
+        new_title = title.to_string();
+    }
+
+    Ok(Some(TitleRewrite {
+        title: new_title,
+        date_text: trim_separators(&title[title_date.start..title_date.end]).to_string(),
+        pattern: title_date.pattern.clone(),
+        created_date: title_date.created_date(settings),
+        range: title_date.range(),
+    }))
+}
+
+/// removes the date from the title and cleans up what is left around it
+pub fn strip_title_date(title: &str, title_date: &TitleDate) -> String {
+    strip_range(title, title_date.start..title_date.end)
+}
+
+/// removes a part of the title, like a date or a tag, and cleans up what is left around it
+///
+/// separators adjoining the part are merged into a single one, dangling dashes at the start or
+/// end of the title are removed and multiple spaces are collapsed.
+pub fn strip_range(title: &str, range: Range<usize>) -> String {
+    let before = title[..range.start].trim_end_matches(is_separator);
+    let after = title[range.end..].trim_start_matches(is_separator);
+    let part = title[range.start..range.end].trim_end_matches(is_separator);
+
+    // everything between the remaining parts apart from the part itself
+    let gap = format!(
+        "{}{}",
+        &title[before.len()..range.start],
+        &title[range.start + part.len()..title.len() - after.len()]
+    );
+
+    // the first dash of the gap is kept, so en and em dashes stay as written
+    let separator = match gap.chars().find(|c| is_dash(*c)) {
+        _ if before.is_empty() || after.is_empty() => String::new(),
+        Some(dash) => format!(" {dash} "),
+        None => " ".to_string(),
+    };
+
+    format!("{before}{separator}{after}")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_matches(|c: char| c.is_whitespace() || is_dash(c))
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(position: DatePosition, partial_dates: PartialDates) -> DateSettings {
+        DateSettings {
+            patterns: DatePattern::builtins(DateOrder::DayMonthYear),
+            position,
+            partial_dates,
+            range_date: RangeDate::Start,
+            min_year: MIN_YEAR_DEFAULT,
+            max_year: 2100,
+            century_pivot: CENTURY_PIVOT_DEFAULT,
+        }
+    }
+
+    fn date(text: &str) -> NaiveDate {
+        text.parse().unwrap()
+    }
+
+    /// rewrites the title, expecting a date to be found
+    fn rewrite(title: &str, settings: &DateSettings) -> TitleRewrite {
+        rewrite_title(title, settings)
+            .unwrap_or_else(|err| panic!("invalid date in '{title}': {err}"))
+            .unwrap_or_else(|| panic!("no date found in '{title}'"))
+    }
+
+    /// checks the rewritten title and created date
+    fn assert_rewrite(title: &str, settings: &DateSettings, new_title: &str, created_date: &str) {
+        let rewrite = rewrite(title, settings);
+        assert_eq!(rewrite.title, new_title, "title of '{title}'");
+        assert_eq!(
+            rewrite.created_date,
+            Some(date(created_date)),
+            "created date of '{title}'"
+        );
+    }
+
+    fn assert_no_date(title: &str, settings: &DateSettings) {
+        let rewrite = rewrite_title(title, settings);
+        assert!(
+            matches!(rewrite, Ok(None)),
+            "expected no date in '{title}', got {rewrite:?}"
+        );
+    }
+
+    fn assert_invalid(title: &str, settings: &DateSettings) {
+        let rewrite = rewrite_title(title, settings);
+        assert!(
+            rewrite.is_err(),
+            "expected an invalid date in '{title}', got {rewrite:?}"
+        );
+    }
+
+    #[test]
+    fn separated_dates() {
+        let settings = settings(DatePosition::Start, PartialDates::Off);
+
+        assert_rewrite("2024-03-15 - Rechnung", &settings, "Rechnung", "2024-03-15");
+        assert_rewrite("15.03.2024 Rechnung", &settings, "Rechnung", "2024-03-15");
+        assert_rewrite("2024/3/5 Scan", &settings, "Scan", "2024-03-05");
+        assert_no_date("Rechnung 2024-03-15", &settings);
+    }
+
+    #[test]
+    fn slash_dates_follow_the_date_order() {
+        let day_month = settings(DatePosition::Start, PartialDates::Off);
+        let mut month_day = settings(DatePosition::Start, PartialDates::Off);
+        month_day.patterns = DatePattern::builtins(DateOrder::MonthDayYear);
+
+        assert_rewrite("03/04/2024 Brief", &day_month, "Brief", "2024-04-03");
+        assert_rewrite("03/04/2024 Brief", &month_day, "Brief", "2024-03-04");
+
+        // the other order is used when the preferred one gives no valid date
+        assert_rewrite("13/04/2024 Brief", &month_day, "Brief", "2024-04-13");
+        assert_rewrite("04/13/2024 Brief", &day_month, "Brief", "2024-04-13");
+    }
+
+    #[test]
+    fn date_order_setting() {
+        assert_eq!(DateOrder::from_setting("DMY"), DateOrder::DayMonthYear);
+        assert_eq!(DateOrder::from_setting("MDY"), DateOrder::MonthDayYear);
+        assert_eq!(DateOrder::from_setting("YMD"), DateOrder::MonthDayYear);
+        assert_eq!(DateOrder::from_setting("ydm"), DateOrder::DayMonthYear);
+    }
+
+    #[test]
+    fn titles_of_nothing_but_a_date_are_kept() {
+        let start = settings(DatePosition::Start, PartialDates::Keep);
+        let first = settings(DatePosition::First, PartialDates::Off);
+
+        assert_rewrite("2024-03-15 - - -", &start, "2024-03-15 - - -", "2024-03-15");
+        assert_rewrite("-2024-03-15-", &first, "-2024-03-15-", "2024-03-15");
+
+        let rewrite = rewrite("Q1 2024", &start);
+        assert_eq!(rewrite.title, "Q1 2024");
+        assert_eq!(rewrite.created_date, None);
+    }
+
+    #[test]
+    fn compact_dates() {
+        let settings = settings(DatePosition::Start, PartialDates::Off);
+
+        assert_rewrite("20240315 Scan", &settings, "Scan", "2024-03-15");
+        assert_rewrite("20240315_143002 Scan", &settings, "Scan", "2024-03-15");
+        assert_rewrite(
+            "20240315T143002",
+            &settings,
+            "20240315T143002",
+            "2024-03-15",
+        );
+        assert_rewrite("20240315-143002-Scan", &settings, "Scan", "2024-03-15");
+        assert_rewrite("20240315235959 Scan", &settings, "Scan", "2024-03-15");
+
+        // a number following after a space or no valid time is kept
+        assert_rewrite(
+            "20240315 123456 Rechnung",
+            &settings,
+            "123456 Rechnung",
+            "2024-03-15",
+        );
+        assert_rewrite(
+            "20240315_996199 Scan",
+            &settings,
+            "996199 Scan",
+            "2024-03-15",
+        );
+        assert_rewrite(
+            "20240315_240000 Scan",
+            &settings,
+            "240000 Scan",
+            "2024-03-15",
+        );
+    }
+
+    #[test]
+    fn month_names() {
+        let settings = settings(DatePosition::Start, PartialDates::Off);
+
+        assert_rewrite(
+            "15. März 2024 Rechnung",
+            &settings,
+            "Rechnung",
+            "2024-03-15",
+        );
+        assert_rewrite(
+            "15 March 2024 - Invoice",
+            &settings,
+            "Invoice",
+            "2024-03-15",
+        );
+        assert_rewrite("15th Mar 2024 Invoice", &settings, "Invoice", "2024-03-15");
+        assert_rewrite("March 15, 2024 Invoice", &settings, "Invoice", "2024-03-15");
+        assert_rewrite(
+            "Dez 1st 2023 Abrechnung",
+            &settings,
+            "Abrechnung",
+            "2023-12-01",
+        );
+        assert_no_date("15 Stück 2024 Lieferschein", &settings);
+    }
+
+    #[test]
+    fn leap_days() {
+        let settings = settings(DatePosition::Start, PartialDates::Off);
+
+        assert_rewrite("2024-02-29 Scan", &settings, "Scan", "2024-02-29");
+        assert_rewrite("29.02.2000 Scan", &settings, "Scan", "2000-02-29");
+        assert_invalid("2023-02-29 Scan", &settings);
+        assert_invalid("29.02.1900 Scan", &settings);
+    }
+
+    #[test]
+    fn invalid_dates() {
+        let complete = settings(DatePosition::Start, PartialDates::Off);
+        let partial = settings(DatePosition::Start, PartialDates::Start);
+
+        assert_invalid("2024-13-01 Scan", &complete);
+        assert_invalid("31.04.2024 Scan", &complete);
+        assert_invalid("1850-01-01 Scan", &complete);
+        assert_invalid("15/13/2024 Scan", &complete);
+        assert_invalid("32. März 2024 Scan", &complete);
+        assert_invalid("2024-13 Scan", &partial);
+    }
+
+    #[test]
+    fn numbers_are_no_invalid_dates() {
+        let settings = settings(DatePosition::Start, PartialDates::Start);
+
+        assert_no_date("12345678 Rechnung", &settings);
+        assert_no_date("20241399 Rechnung", &settings);
+        assert_no_date("12-34-56 Part", &settings);
+        assert_no_date("45.67.89 Part", &settings);
+        assert_no_date("1000 Stück Lieferschein", &settings);
+        assert_no_date("99/99/99 Part", &settings);
+    }
+
+    #[test]
+    fn two_digit_years() {
+        let settings = settings(DatePosition::Start, PartialDates::Off);
+
+        assert_rewrite("15.03.24 Rechnung", &settings, "Rechnung", "2024-03-15");
+        assert_rewrite("24-03-15 Rechnung", &settings, "Rechnung", "2024-03-15");
+        assert_rewrite("15.03.69 Rechnung", &settings, "Rechnung", "2069-03-15");
+        assert_rewrite("15.03.70 Rechnung", &settings, "Rechnung", "1970-03-15");
+        assert_rewrite("15/03/85 Rechnung", &settings, "Rechnung", "1985-03-15");
+    }
+
+    #[test]
+    fn first_and_last_date() {
+        let first = settings(DatePosition::First, PartialDates::Off);
+        let last = settings(DatePosition::Last, PartialDates::Off);
+
+        let title = "Scan 2024-01-10 Rechnung vom 15.03.2024";
+        assert_rewrite(title, &first, "Scan Rechnung vom 15.03.2024", "2024-01-10");
+        assert_rewrite(title, &last, "Scan 2024-01-10 Rechnung vom", "2024-03-15");
+        assert_rewrite(
+            "Ärger – 2024-03-15 — Foo",
+            &last,
+            "Ärger – Foo",
+            "2024-03-15",
+        );
+        assert_no_date("Kundennummer 12345678 Rechnung", &first);
+        assert_no_date("Rechnung 2024-13-45", &last);
+        assert_rewrite(
+            "Rechnung 2024-13-45 vom 15.03.2024",
+            &first,
+            "Rechnung 2024-13-45 vom",
+            "2024-03-15",
+        );
+        assert_rewrite(
+            "Rechnung - 2024-03-15 - Telekom",
+            &first,
+            "Rechnung - Telekom",
+            "2024-03-15",
+        );
+    }
+
+    #[test]
+    fn partial_dates() {
+        let start = settings(DatePosition::Start, PartialDates::Start);
+        let end = settings(DatePosition::Start, PartialDates::End);
+
+        assert_no_date(
+            "2024-03 Kontoauszug",
+            &settings(DatePosition::Start, PartialDates::Off),
+        );
+        assert_rewrite("2024-03 Kontoauszug", &start, "Kontoauszug", "2024-03-01");
+        assert_rewrite("2024-03 Kontoauszug", &end, "Kontoauszug", "2024-03-31");
+        assert_rewrite("02/2024 Kontoauszug", &end, "Kontoauszug", "2024-02-29");
+        assert_rewrite("März 2024 Kontoauszug", &start, "Kontoauszug", "2024-03-01");
+        assert_rewrite(
+            "2023 Steuererklärung",
+            &end,
+            "Steuererklärung",
+            "2023-12-31",
+        );
+
+        let keep = rewrite(
+            "2024-03 Kontoauszug",
+            &settings(DatePosition::Start, PartialDates::Keep),
+        );
+        assert_eq!(keep.title, "Kontoauszug");
+        assert_eq!(keep.created_date, None);
+    }
+
+    #[test]
+    fn last_partial_date_is_not_cut() {
+        let last = settings(DatePosition::Last, PartialDates::Start);
+
+        assert_rewrite("Bericht März 2024", &last, "Bericht", "2024-03-01");
+        assert_rewrite("March 2024 Statement", &last, "Statement", "2024-03-01");
+        assert_rewrite("Kontoauszug 03/2024", &last, "Kontoauszug", "2024-03-01");
+        assert_rewrite("Kontoauszug 2024-03", &last, "Kontoauszug", "2024-03-01");
+        assert_rewrite(
+            "Steuer 2023 Bescheid 2024",
+            &last,
+            "Steuer 2023 Bescheid",
+            "2024-01-01",
+        );
+    }
+
+    #[test]
+    fn quarters_and_weeks() {
+        let start = settings(DatePosition::Start, PartialDates::Start);
+        let end = settings(DatePosition::Start, PartialDates::End);
+
+        assert_rewrite("2024-Q1 Bericht", &start, "Bericht", "2024-01-01");
+        assert_rewrite("Q2 2024 Bericht", &end, "Bericht", "2024-06-30");
+        assert_rewrite("Q3/2024 Bericht", &start, "Bericht", "2024-07-01");
+        assert_rewrite("1. Quartal 2024 Bericht", &start, "Bericht", "2024-01-01");
+        assert_rewrite(
+            "KW 12/2024 Stundenzettel",
+            &start,
+            "Stundenzettel",
+            "2024-03-18",
+        );
+        assert_rewrite(
+            "KW12 2024 Stundenzettel",
+            &end,
+            "Stundenzettel",
+            "2024-03-24",
+        );
+        assert_rewrite(
+            "2024-W01 Stundenzettel",
+            &start,
+            "Stundenzettel",
+            "2024-01-01",
+        );
+        assert_invalid("KW 53/2024 Stundenzettel", &start);
+    }
+
+    #[test]
+    fn quarters_and_weeks_anywhere_in_the_title() {
+        for position in [DatePosition::First, DatePosition::Last] {
+            let settings = settings(position, PartialDates::Start);
+
+            assert_rewrite(
+                "KW 12/2024 Stundenzettel",
+                &settings,
+                "Stundenzettel",
+                "2024-03-18",
+            );
+            assert_rewrite(
+                "Stundenzettel KW 12/2024",
+                &settings,
+                "Stundenzettel",
+                "2024-03-18",
+            );
+            assert_rewrite(
+                "Stundenzettel 2024-W12",
+                &settings,
+                "Stundenzettel",
+                "2024-03-18",
+            );
+            assert_rewrite(
+                "Stundenzettel CW12-2024 Max",
+                &settings,
+                "Stundenzettel Max",
+                "2024-03-18",
+            );
+            assert_rewrite("Abrechnung Q1/2024", &settings, "Abrechnung", "2024-01-01");
+            assert_rewrite("Q2 2024 Abrechnung", &settings, "Abrechnung", "2024-04-01");
+            assert_rewrite("Abrechnung 2024 Q3", &settings, "Abrechnung", "2024-07-01");
+            assert_rewrite(
+                "1. Quartal 2024 Bericht",
+                &settings,
+                "Bericht",
+                "2024-01-01",
+            );
+            assert_rewrite(
+                "Bericht 4. Quartal 2023",
+                &settings,
+                "Bericht",
+                "2023-10-01",
+            );
+        }
+    }
+
+    #[test]
+    fn date_ranges() {
+        let mut settings = settings(DatePosition::Start, PartialDates::Start);
+
+        let rewrite_range = rewrite("2024-01-01 - 2024-03-31 Kontoauszug", &settings);
+        assert_eq!(rewrite_range.title, "Kontoauszug");
+        assert_eq!(rewrite_range.created_date, Some(date("2024-01-01")));
+        assert_eq!(
+            rewrite_range.range,
+            Some((date("2024-01-01"), date("2024-03-31")))
+        );
+
+        assert_rewrite(
+            "01.01.2024 bis 31.03.2024 Kontoauszug",
+            &settings,
+            "Kontoauszug",
+            "2024-01-01",
+        );
+
+        let months = rewrite("2024-01 - 2024-03 Kontoauszug", &settings);
+        assert_eq!(months.range, Some((date("2024-01-01"), date("2024-03-31"))));
+
+        settings.range_date = RangeDate::End;
+        assert_rewrite(
+            "2024-01-01 - 2024-03-31 Kontoauszug",
+            &settings,
+            "Kontoauszug",
+            "2024-03-31",
+        );
+    }
+
+    #[test]
+    fn custom_patterns() {
+        let mut settings = settings(DatePosition::Start, PartialDates::Off);
+        settings.patterns = vec![DatePattern::new(
+            "underscore",
+            r"(?<year>[0-9]{4})_(?<month>[0-9]{2})_(?<day>[0-9]{2})\s*-?\s*",
+        )
+        .unwrap()];
+
+        assert_rewrite("2024_03_15 - Rechnung", &settings, "Rechnung", "2024-03-15");
+        assert_no_date("2024-03-15 Rechnung", &settings);
+        assert!(DatePattern::new("no year", r"(?<month>[0-9]{2})").is_err());
+        assert!(DatePattern::new("no month", r"(?<year>[0-9]{4})(?<day>[0-9]{2})").is_err());
+    }
+
+    #[test]
+    fn strip_range_cleans_up_separators() {
+        let strip = |title: &str, part: &str| {
+            let start = title.find(part).unwrap();
+            strip_range(title, start..start + part.len())
+        };
+
+        assert_eq!(
+            strip("Rechnung - 2024 - Telekom", "2024"),
+            "Rechnung - Telekom"
+        );
+        assert_eq!(strip("Rechnung 2024 Telekom", "2024"), "Rechnung Telekom");
+        assert_eq!(strip("Rechnung_2024 Telekom", "2024"), "Rechnung Telekom");
+        assert_eq!(strip("2024 - Telekom", "2024"), "Telekom");
+        assert_eq!(strip("Telekom  -  2024", "2024"), "Telekom");
+        assert_eq!(
+            strip("Arztrechnung #medical #tax", "#medical"),
+            "Arztrechnung #tax"
+        );
+        assert_eq!(strip("[Steuer]   Bescheid", "[Steuer]"), "Bescheid");
+
+        // en and em dashes are separators as well
+        assert_eq!(
+            strip("Ärger – 2024-03-15 — Foo", "2024-03-15"),
+            "Ärger – Foo"
+        );
+        assert_eq!(strip("2024 — Telekom", "2024"), "Telekom");
+        assert_eq!(strip("Telekom –2024", "2024"), "Telekom");
+    }
+}